@@ -10,6 +10,10 @@ pub const MIN_FREQUENCY: u32 = 7 * 86400; // 7 days in seconds. u32 since maximu
 pub const MIN_FEE_FOR_CONTRACT_BPS: u16 = 75; // 0.75%
 pub const MAX_FEE_BPS: u16 = 1000; // 10%
 
+pub const MAX_SPLIT_RECIPIENTS: usize = 10; // Maximum number of recipients of a split payment (bounded by the transaction size and compute budget)
+
+pub const MAX_AUTHORIZATION_PERIOD: i64 = 30 * 86400; // 30 days in seconds. Maximum duration of a payment authorization (hold)
+
 
 
 // PDA seeds
@@ -20,4 +24,25 @@ pub const AUTHORITY_PDA_SEED: &[u8] = b"authority";
 pub const SUBSCRIPTION_PDA_SEED: &[u8] = b"subscription";
 
 #[constant]
-pub const PERMIT_REGISTRY_PDA_SEED: &[u8] = b"permit_registry";
\ No newline at end of file
+pub const PLAN_PDA_SEED: &[u8] = b"plan";
+
+#[constant]
+pub const PERMIT_REGISTRY_PDA_SEED: &[u8] = b"permit_registry";
+
+#[constant]
+pub const PAYMENT_RECEIPT_PDA_SEED: &[u8] = b"payment_receipt";
+
+#[constant]
+pub const AUTHORIZATION_PDA_SEED: &[u8] = b"authorization";
+
+#[constant]
+pub const AUTHORIZATION_VAULT_PDA_SEED: &[u8] = b"authorization_vault";
+
+#[constant]
+pub const ESCROW_PDA_SEED: &[u8] = b"escrow";
+
+#[constant]
+pub const ESCROW_VAULT_PDA_SEED: &[u8] = b"escrow_vault";
+
+#[constant]
+pub const ESCROW_CONFIG_PDA_SEED: &[u8] = b"escrow_config";
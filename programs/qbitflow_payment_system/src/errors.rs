@@ -45,4 +45,68 @@ pub enum QBitFlowError {
 
 	#[msg("Max amount lower than last payment")]
 	MaxAmountInvalid,
+
+	#[msg("Refund exceeds the amount paid")]
+	RefundExceedsPayment,
+
+	#[msg("Missing or invalid token accounts")]
+	InvalidTokenAccounts,
+
+	#[msg("Authorization has expired")]
+	AuthorizationExpired,
+
+	#[msg("Authorization has not expired yet")]
+	AuthorizationNotExpired,
+
+	InvalidExpiration,
+
+	#[msg("Escrow cannot be released yet")]
+	EscrowLocked,
+
+	#[msg("Escrow is disputed")]
+	EscrowDisputed,
+
+	#[msg("Escrow is not disputed")]
+	EscrowNotDisputed,
+
+	#[msg("Dispute window has closed")]
+	DisputeWindowClosed,
+
+	#[msg("Split shares do not sum to the net amount")]
+	InvalidSplitShares,
+
+	#[msg("Subscription account is already migrated")]
+	SubscriptionAlreadyMigrated,
+
+	#[msg("Plan is not active")]
+	PlanInactive,
+
+	#[msg("Subscription is paused")]
+	SubscriptionPaused,
+	#[msg("Subscription is not paused")]
+	SubscriptionNotPaused,
+
+	#[msg("Invalid subscription status transition")]
+	InvalidStatusTransition,
+	#[msg("Subscription has ended")]
+	SubscriptionEnded,
+
+	#[msg("Plan cannot be changed in the current billing state")]
+	PlanChangeNotAllowed,
+
+	#[msg("Price change effective date must be at least one billing period away")]
+	InvalidEffectiveDate,
+	#[msg("No pending price change for this subscription")]
+	NoPendingPriceChange,
+	#[msg("Pending price change has expired")]
+	PriceChangeExpired,
+
+	#[msg("Subscription has not been canceled")]
+	SubscriptionNotCanceled,
+
+	#[msg("Every subscription of the permit registry must be provided")]
+	SubscriptionsRemaining,
+
+	#[msg("Resuming would move the next payment past the end of the subscription")]
+	ResumePastEndDate,
 }
\ No newline at end of file
@@ -0,0 +1,415 @@
+use anchor_lang::prelude::*;
+use anchor_spl::associated_token::AssociatedToken;
+use anchor_spl::token_interface::{self, CloseAccount, HarvestWithheldTokensToMint, Mint, TokenAccount, TokenInterface, TransferChecked};
+use crate::instructions::compute_refund::{compute_refund, ComputeRefundData};
+use crate::instructions::transfer_fee::{get_transfer_fee_config, TransferAmounts, TransferFeePolicy};
+use crate::instructions::transfer_hook::transfer_checked_with_hook;
+use crate::state::*;
+use crate::errors::*;
+use crate::{AUTHORIZATION_PDA_SEED, AUTHORIZATION_VAULT_PDA_SEED, MAX_AUTHORIZATION_PERIOD};
+
+#[derive(Accounts)]
+#[instruction(uuid: [u8; 16])]
+pub struct AuthorizePayment<'info> {
+	#[account()]
+	pub authority_and_owner: AuthorityAndOwner<'info>,
+
+	// The payer is the one authorizing the payment
+	#[account(mut)]
+	pub payer: Signer<'info>,
+
+	// ATA of the payer, from which the held funds are taken
+	#[account(
+		mut,
+		associated_token::mint = mint,
+		associated_token::authority = payer,
+		associated_token::token_program = token_program
+	)]
+	pub payer_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+	/// CHECK: The merchant allowed to capture the payment
+	pub merchant: UncheckedAccount<'info>,
+
+	// Unique authorization account derived from uuid
+	// Raises an error if an authorization with the same uuid already exists
+	#[account(
+		init,
+		payer = authority_and_owner.owner, // authority owner pays for the authorization account creation (and is reimbursed when it's closed)
+		space = PaymentAuthorization::LEN,
+		seeds = [AUTHORIZATION_PDA_SEED, uuid.as_ref()],
+		bump
+	)]
+	pub authorization: Account<'info, PaymentAuthorization>,
+
+	// Vault holding the funds until capture or void, owned by the authority PDA
+	#[account(
+		init,
+		payer = authority_and_owner.owner,
+		seeds = [AUTHORIZATION_VAULT_PDA_SEED, uuid.as_ref()],
+		bump,
+		token::mint = mint,
+		token::authority = authority_and_owner.authority,
+		token::token_program = token_program
+	)]
+	pub vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+	// Ensures this token account belongs to authority.owner (compute refund recipient)
+	#[account(
+		init_if_needed,
+		payer = authority_and_owner.owner,
+		associated_token::mint = mint,
+		associated_token::authority = authority_and_owner.owner, // fee recipient is authority owner
+		associated_token::token_program = token_program
+	)]
+	pub fee_recipient_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+	// The mint of the token being held (Token or Token-2022)
+	pub mint: Box<InterfaceAccount<'info, Mint>>,
+
+	pub system_program: Program<'info, System>,
+	pub token_program: Interface<'info, TokenInterface>,
+	pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+#[instruction(uuid: [u8; 16])]
+pub struct CapturePayment<'info> {
+	#[account()]
+	pub authority_and_owner: AuthorityAndOwner<'info>,
+
+	#[account(
+		mut,
+		seeds = [AUTHORIZATION_PDA_SEED, uuid.as_ref()],
+		bump = authorization.bump,
+		has_one = merchant @ QBitFlowError::Unauthorized, // Only the merchant of the authorization can capture it
+		has_one = mint @ QBitFlowError::InvalidTokenAccounts,
+	)]
+	pub authorization: Account<'info, PaymentAuthorization>,
+
+	#[account(
+		mut,
+		seeds = [AUTHORIZATION_VAULT_PDA_SEED, uuid.as_ref()],
+		bump
+	)]
+	pub vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+	// The merchant capturing the payment, needs to sign the transaction
+	#[account(mut)]
+	pub merchant: Signer<'info>,
+
+	// The merchant's token account receiving the captured payment
+	#[account(
+		init_if_needed,
+		payer = authority_and_owner.owner, // authority pays for token account initialization (merchant)
+		associated_token::mint = mint,
+		associated_token::authority = merchant,
+		associated_token::token_program = token_program
+	)]
+	pub merchant_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+	// ATA of the payer, receiving the part of the hold that is not captured
+	#[account(
+		mut,
+		associated_token::mint = mint,
+		associated_token::authority = authorization.payer,
+		associated_token::token_program = token_program
+	)]
+	pub payer_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+	// Ensures this token account belongs to authority.owner
+	#[account(
+		init_if_needed,
+		payer = authority_and_owner.owner,
+		associated_token::mint = mint,
+		associated_token::authority = authority_and_owner.owner, // fee recipient is authority owner
+		associated_token::token_program = token_program
+	)]
+	pub fee_recipient_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+	/// CHECK: This is the organization receiving a portion of the fees. Must be initialized
+	#[account(mut)]
+	pub organization: UncheckedAccount<'info>,
+
+	#[account(
+		init_if_needed,
+		payer = authority_and_owner.owner, // authority pays for token account initialization (organization fee recipient)
+		associated_token::mint = mint,
+		associated_token::authority = organization,
+		associated_token::token_program = token_program
+	)]
+	pub organization_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+	// Writable so the transfer fees withheld in the vault can be harvested before closing it
+	#[account(mut)]
+	pub mint: Box<InterfaceAccount<'info, Mint>>,
+
+	pub system_program: Program<'info, System>,
+	pub token_program: Interface<'info, TokenInterface>,
+	pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+#[instruction(uuid: [u8; 16])]
+pub struct VoidAuthorization<'info> {
+	#[account()]
+	pub authority_and_owner: AuthorityAndOwner<'info>,
+
+	#[account(
+		mut,
+		seeds = [AUTHORIZATION_PDA_SEED, uuid.as_ref()],
+		bump = authorization.bump,
+		has_one = mint @ QBitFlowError::InvalidTokenAccounts,
+	)]
+	pub authorization: Account<'info, PaymentAuthorization>,
+
+	#[account(
+		mut,
+		seeds = [AUTHORIZATION_VAULT_PDA_SEED, uuid.as_ref()],
+		bump
+	)]
+	pub vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+	// The merchant of the authorization. Required before the expiration, optional after
+	#[account(address = authorization.merchant @ QBitFlowError::Unauthorized)]
+	pub merchant: Option<Signer<'info>>,
+
+	// ATA of the payer, receiving the whole hold
+	#[account(
+		mut,
+		associated_token::mint = mint,
+		associated_token::authority = authorization.payer,
+		associated_token::token_program = token_program
+	)]
+	pub payer_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+	// Writable so the transfer fees withheld in the vault can be harvested before closing it
+	#[account(mut)]
+	pub mint: Box<InterfaceAccount<'info, Mint>>,
+
+	pub token_program: Interface<'info, TokenInterface>,
+}
+
+
+/**
+ * Authorize (hold) a token payment
+ * The funds are moved from the payer to a vault owned by the authority PDA, until the merchant captures them (fully or partially), or the authorization is voided
+ * The authorization expires at `expires_at` (at most MAX_AUTHORIZATION_PERIOD from now): it can then no longer be captured, and can be voided without the merchant
+ * Emits a PaymentAuthorized event
+ */
+pub fn authorize_payment<'info>(
+	ctx: Context<'_, '_, '_, 'info, AuthorizePayment<'info>>,
+	uuid: [u8; 16],
+	amount: u64,
+	expires_at: i64,
+	compute_refund_params: ComputeRefundData,
+	transfer_fee_policy: TransferFeePolicy,
+) -> Result<()> {
+	if amount == 0 {
+		return err!(QBitFlowError::ZeroAmount);
+	}
+
+	let current_time = Clock::get()?.unix_timestamp;
+	if expires_at <= current_time || expires_at > current_time + MAX_AUTHORIZATION_PERIOD {
+		return err!(QBitFlowError::InvalidExpiration);
+	}
+
+	// Move the funds to the vault. The amount held is the amount that actually lands in the vault (after the Token-2022 transfer fee, if any)
+	let hold_transfer = TransferAmounts::compute(&ctx.accounts.mint.to_account_info(), amount, transfer_fee_policy)?;
+
+	transfer_checked_with_hook(
+		CpiContext::new(
+			ctx.accounts.token_program.to_account_info(),
+			TransferChecked {
+				from: ctx.accounts.payer_token_account.to_account_info(),
+				mint: ctx.accounts.mint.to_account_info(),
+				to: ctx.accounts.vault.to_account_info(),
+				authority: ctx.accounts.payer.to_account_info(),
+			},
+		).with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+		hold_transfer.gross,
+		ctx.accounts.mint.decimals,
+	)?;
+
+	// Compute refund in tokens for the authority owner to refund the compute cost paid in SOL
+	let _ = compute_refund(uuid, 0, compute_refund_params, ctx.accounts.mint.decimals, CpiContext::new(
+		ctx.accounts.token_program.to_account_info(),
+		TransferChecked {
+			from: ctx.accounts.payer_token_account.to_account_info(),
+			mint: ctx.accounts.mint.to_account_info(),
+			to: ctx.accounts.fee_recipient_token_account.to_account_info(),
+			authority: ctx.accounts.payer.to_account_info(),
+		},
+	).with_remaining_accounts(ctx.remaining_accounts.to_vec()));
+
+	ctx.accounts.authorization.set_inner(PaymentAuthorization {
+		payer: ctx.accounts.payer.key(),
+		merchant: ctx.accounts.merchant.key(),
+		mint: ctx.accounts.mint.key(),
+		amount: hold_transfer.net,
+		expires_at,
+		bump: ctx.bumps.authorization,
+	});
+
+	emit!(PaymentAuthorized {
+		uuid,
+		amount: hold_transfer.net,
+		expires_at,
+	});
+
+	Ok(())
+}
+
+
+/**
+ * Capture an authorized payment (signed by the merchant)
+ * Up to the held amount can be captured, fees are computed on the captured amount (like a regular payment)
+ * The rest of the hold is returned to the payer, and the authorization and vault are closed (rent returned to the authority owner)
+ * Emits a PaymentCaptured event
+ */
+pub fn capture_payment<'info>(
+	ctx: Context<'_, '_, '_, 'info, CapturePayment<'info>>,
+	uuid: [u8; 16],
+	amount: u64,
+	fee_bps: u16,
+	organization_fee_bps: u16,
+) -> Result<()> {
+	let authorization = &ctx.accounts.authorization;
+
+	if Clock::get()?.unix_timestamp >= authorization.expires_at {
+		return err!(QBitFlowError::AuthorizationExpired);
+	}
+
+	// Up to the held amount can be captured, the rest is released
+	let (fee_amount, org_fee_amount, remaining_amount, released_amount) = authorization.split_capture(amount, fee_bps, organization_fee_bps)?;
+
+	// The funds are already in the vault, so the recipients absorb the Token-2022 transfer fee (if any)
+	let mint_info = ctx.accounts.mint.to_account_info();
+	let fee_transfer = TransferAmounts::compute(&mint_info, fee_amount, TransferFeePolicy::RecipientsAbsorb)?;
+	let org_fee_transfer = TransferAmounts::compute(&mint_info, org_fee_amount, TransferFeePolicy::RecipientsAbsorb)?;
+	let merchant_transfer = TransferAmounts::compute(&mint_info, remaining_amount, TransferFeePolicy::RecipientsAbsorb)?;
+
+	let accounts = &ctx.accounts;
+	transfer_from_vault(&accounts.token_program, &accounts.vault, &accounts.mint, accounts.fee_recipient_token_account.to_account_info(), &accounts.authority_and_owner.authority, ctx.remaining_accounts, fee_transfer.gross)?;
+	transfer_from_vault(&accounts.token_program, &accounts.vault, &accounts.mint, accounts.organization_token_account.to_account_info(), &accounts.authority_and_owner.authority, ctx.remaining_accounts, org_fee_transfer.gross)?;
+	transfer_from_vault(&accounts.token_program, &accounts.vault, &accounts.mint, accounts.merchant_token_account.to_account_info(), &accounts.authority_and_owner.authority, ctx.remaining_accounts, merchant_transfer.gross)?;
+
+	// Return the rest of the hold to the payer
+	transfer_from_vault(&accounts.token_program, &accounts.vault, &accounts.mint, accounts.payer_token_account.to_account_info(), &accounts.authority_and_owner.authority, ctx.remaining_accounts, released_amount)?;
+
+	// Close the vault and the authorization, rent goes back to the authority owner (who paid for them)
+	close_vault(&accounts.token_program, &accounts.vault, &accounts.mint, accounts.authority_and_owner.owner.to_account_info(), &accounts.authority_and_owner.authority)?;
+	ctx.accounts.authorization.close(ctx.accounts.authority_and_owner.owner.to_account_info())?;
+
+	emit!(PaymentCaptured {
+		uuid,
+		amount,
+		merchant_amount: merchant_transfer.net,
+		fee_amount: fee_transfer.net,
+		organization_fee_amount: org_fee_transfer.net,
+		released_amount,
+	});
+
+	Ok(())
+}
+
+
+/**
+ * Void an authorized payment, returning the whole hold to the payer
+ * Before the expiration, this must be signed by the merchant. After the expiration, the authority owner can void it alone
+ * The authorization and vault are closed (rent returned to the authority owner)
+ * Emits an AuthorizationVoided event
+ */
+pub fn void_authorization<'info>(
+	ctx: Context<'_, '_, '_, 'info, VoidAuthorization<'info>>,
+	uuid: [u8; 16],
+) -> Result<()> {
+	let authorization = &ctx.accounts.authorization;
+
+	let expired = Clock::get()?.unix_timestamp >= authorization.expires_at;
+	if !expired && ctx.accounts.merchant.is_none() {
+		return err!(QBitFlowError::AuthorizationNotExpired);
+	}
+
+	let released_amount = authorization.amount;
+
+	let accounts = &ctx.accounts;
+	transfer_from_vault(&accounts.token_program, &accounts.vault, &accounts.mint, accounts.payer_token_account.to_account_info(), &accounts.authority_and_owner.authority, ctx.remaining_accounts, released_amount)?;
+
+	close_vault(&accounts.token_program, &accounts.vault, &accounts.mint, accounts.authority_and_owner.owner.to_account_info(), &accounts.authority_and_owner.authority)?;
+	ctx.accounts.authorization.close(ctx.accounts.authority_and_owner.owner.to_account_info())?;
+
+	emit!(AuthorizationVoided {
+		uuid,
+		released_amount,
+		expired,
+	});
+
+	Ok(())
+}
+
+
+// Transfer tokens out of a vault owned by the authority PDA (the authority PDA signs the transfer)
+#[inline(never)]
+pub(crate) fn transfer_from_vault<'info>(
+	token_program: &Interface<'info, TokenInterface>,
+	vault: &InterfaceAccount<'info, TokenAccount>,
+	mint: &InterfaceAccount<'info, Mint>,
+	to: AccountInfo<'info>,
+	authority: &Account<'info, Authority>,
+	remaining_accounts: &[AccountInfo<'info>],
+	amount: u64,
+) -> Result<()> {
+	if amount == 0 {
+		return Ok(());
+	}
+
+	transfer_checked_with_hook(
+		CpiContext::new_with_signer(
+			token_program.to_account_info(),
+			TransferChecked {
+				from: vault.to_account_info(),
+				mint: mint.to_account_info(),
+				to,
+				authority: authority.to_account_info(),
+			},
+			&[&authority.get_seeds()]
+		).with_remaining_accounts(remaining_accounts.to_vec()),
+		amount,
+		mint.decimals,
+	)
+}
+
+
+// Close an (empty) vault owned by the authority PDA, and send its rent to the destination
+// Token-2022 refuses to close accounts with withheld transfer fees, so they are harvested to the mint first (permissionless)
+#[inline(never)]
+pub(crate) fn close_vault<'info>(
+	token_program: &Interface<'info, TokenInterface>,
+	vault: &InterfaceAccount<'info, TokenAccount>,
+	mint: &InterfaceAccount<'info, Mint>,
+	destination: AccountInfo<'info>,
+	authority: &Account<'info, Authority>,
+) -> Result<()> {
+	if get_transfer_fee_config(&mint.to_account_info())?.is_some() {
+		token_interface::harvest_withheld_tokens_to_mint(
+			CpiContext::new(
+				token_program.to_account_info(),
+				HarvestWithheldTokensToMint {
+					token_program_id: token_program.to_account_info(),
+					mint: mint.to_account_info(),
+				},
+			),
+			vec![vault.to_account_info()],
+		)?;
+	}
+
+	token_interface::close_account(CpiContext::new_with_signer(
+		token_program.to_account_info(),
+		CloseAccount {
+			account: vault.to_account_info(),
+			destination,
+			authority: authority.to_account_info(),
+		},
+		&[&authority.get_seeds()]
+	))
+}
@@ -1,8 +1,9 @@
 
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Transfer};
+use anchor_spl::token_interface::TransferChecked;
 
 use crate::{errors::*, state::ComputeRefundFailed};
+use crate::instructions::transfer_hook::transfer_checked_with_hook;
 
 #[derive(AnchorSerialize, AnchorDeserialize)]
 pub struct ComputeRefundData {
@@ -14,7 +15,7 @@ pub struct ComputeRefundData {
 // Compute the refund amount for a given user
 // This is used for token-based payments. The owner of the authority pays the compute fees in SOL, the is refunded in tokens by the user to enable gasless transactions
 #[inline(never)]
-pub fn compute_refund<'info>(uuid: [u8; 16], max_amount_refund: u64, params: ComputeRefundData, cpi_context: CpiContext<'_, '_, '_, 'info, Transfer<'info>>) -> Result<u64> {
+pub fn compute_refund<'info>(uuid: [u8; 16], max_amount_refund: u64, params: ComputeRefundData, decimals: u8, cpi_context: CpiContext<'_, '_, '_, 'info, TransferChecked<'info>>) -> Result<u64> {
 	// Compute the number of tokens to refund based on the compute cost and token price
 	if params.token_price_in_lamports == 0 {
 		return Ok(0);
@@ -38,9 +39,11 @@ pub fn compute_refund<'info>(uuid: [u8; 16], max_amount_refund: u64, params: Com
 	// This function doesn't fail if the compute refund fails, it just emits an event
 	// This is to avoid the whole transaction from failing if the compute refund fails
 	// The refund is a best-effort basis, if it fails, the owner of the authority will have to pay the compute fees in SOL without being refunded in tokens. Shouldn't happen often
-	match token::transfer(
+	// The CPI context carries the instruction's remaining accounts, so this also works for mints with a transfer hook
+	match transfer_checked_with_hook(
 		cpi_context,
 		compute_cost_in_tokens,
+		decimals,
 	) {
 		Ok(_) => Ok(compute_cost_in_tokens),
 		Err(_) => {
@@ -0,0 +1,493 @@
+use anchor_lang::prelude::*;
+use anchor_spl::associated_token::AssociatedToken;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface, TransferChecked};
+use crate::instructions::authorizations::{close_vault, transfer_from_vault};
+use crate::instructions::compute_refund::{compute_refund, ComputeRefundData};
+use crate::instructions::transfer_fee::{TransferAmounts, TransferFeePolicy};
+use crate::instructions::transfer_hook::transfer_checked_with_hook;
+use crate::state::*;
+use crate::errors::*;
+use crate::{ESCROW_CONFIG_PDA_SEED, ESCROW_PDA_SEED, ESCROW_VAULT_PDA_SEED};
+
+#[derive(Accounts)]
+#[instruction(uuid: [u8; 16])]
+pub struct CreateEscrowPayment<'info> {
+	#[account()]
+	pub authority_and_owner: AuthorityAndOwner<'info>,
+
+	// The payer is the one funding the escrow
+	#[account(mut)]
+	pub payer: Signer<'info>,
+
+	// ATA of the payer
+	#[account(
+		mut,
+		associated_token::mint = mint,
+		associated_token::authority = payer,
+		associated_token::token_program = token_program
+	)]
+	pub payer_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+	/// CHECK: The merchant receiving the funds on release
+	pub merchant: UncheckedAccount<'info>,
+
+	/// CHECK: The organization receiving a portion of the fees on release
+	pub organization: UncheckedAccount<'info>,
+
+	// Unique escrow account derived from uuid
+	// Raises an error if an escrow with the same uuid already exists
+	#[account(
+		init,
+		payer = authority_and_owner.owner, // authority owner pays for the escrow account creation (and is reimbursed when it's closed)
+		space = Escrow::LEN,
+		seeds = [ESCROW_PDA_SEED, uuid.as_ref()],
+		bump
+	)]
+	pub escrow: Account<'info, Escrow>,
+
+	// Vault holding the funds until release, owned by the authority PDA
+	#[account(
+		init,
+		payer = authority_and_owner.owner,
+		seeds = [ESCROW_VAULT_PDA_SEED, uuid.as_ref()],
+		bump,
+		token::mint = mint,
+		token::authority = authority_and_owner.authority,
+		token::token_program = token_program
+	)]
+	pub vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+	// Ensures this token account belongs to authority.owner (compute refund recipient)
+	#[account(
+		init_if_needed,
+		payer = authority_and_owner.owner,
+		associated_token::mint = mint,
+		associated_token::authority = authority_and_owner.owner, // fee recipient is authority owner
+		associated_token::token_program = token_program
+	)]
+	pub fee_recipient_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+	// The mint of the token held in escrow (Token or Token-2022)
+	pub mint: Box<InterfaceAccount<'info, Mint>>,
+
+	pub system_program: Program<'info, System>,
+	pub token_program: Interface<'info, TokenInterface>,
+	pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+#[instruction(uuid: [u8; 16])]
+pub struct ReleaseEscrow<'info> {
+	#[account()]
+	pub authority_and_owner: AuthorityAndOwner<'info>,
+
+	#[account(
+		mut,
+		seeds = [ESCROW_PDA_SEED, uuid.as_ref()],
+		bump = escrow.bump,
+		has_one = merchant,
+		has_one = organization,
+		has_one = mint @ QBitFlowError::InvalidTokenAccounts,
+	)]
+	pub escrow: Account<'info, Escrow>,
+
+	#[account(
+		mut,
+		seeds = [ESCROW_VAULT_PDA_SEED, uuid.as_ref()],
+		bump
+	)]
+	pub vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+	// The payer of the escrow. Required before release_at, optional after
+	#[account(address = escrow.payer @ QBitFlowError::Unauthorized)]
+	pub payer: Option<Signer<'info>>,
+
+	/// CHECK: The merchant of the escrow (verified against the escrow)
+	pub merchant: UncheckedAccount<'info>,
+
+	#[account(
+		init_if_needed,
+		payer = authority_and_owner.owner, // authority pays for token account initialization (merchant)
+		associated_token::mint = mint,
+		associated_token::authority = merchant,
+		associated_token::token_program = token_program
+	)]
+	pub merchant_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+	#[account(
+		init_if_needed,
+		payer = authority_and_owner.owner,
+		associated_token::mint = mint,
+		associated_token::authority = authority_and_owner.owner, // fee recipient is authority owner
+		associated_token::token_program = token_program
+	)]
+	pub fee_recipient_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+	/// CHECK: The organization of the escrow (verified against the escrow)
+	pub organization: UncheckedAccount<'info>,
+
+	#[account(
+		init_if_needed,
+		payer = authority_and_owner.owner, // authority pays for token account initialization (organization fee recipient)
+		associated_token::mint = mint,
+		associated_token::authority = organization,
+		associated_token::token_program = token_program
+	)]
+	pub organization_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+	// Writable so the transfer fees withheld in the vault can be harvested before closing it
+	#[account(mut)]
+	pub mint: Box<InterfaceAccount<'info, Mint>>,
+
+	pub system_program: Program<'info, System>,
+	pub token_program: Interface<'info, TokenInterface>,
+	pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+#[instruction(uuid: [u8; 16])]
+pub struct DisputeEscrow<'info> {
+	#[account()]
+	pub authority_and_owner: AuthorityAndOwner<'info>,
+
+	#[account(
+		mut,
+		seeds = [ESCROW_PDA_SEED, uuid.as_ref()],
+		bump = escrow.bump,
+		has_one = payer @ QBitFlowError::Unauthorized, // Only the payer can raise a dispute
+	)]
+	pub escrow: Account<'info, Escrow>,
+
+	pub payer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(uuid: [u8; 16])]
+pub struct ResolveEscrowDispute<'info> {
+	#[account()]
+	pub authority_and_owner: AuthorityAndOwner<'info>,
+
+	// Escrow configuration, only exists once set_arbiter has been called (disputes cannot be resolved until then)
+	#[account(
+		seeds = [ESCROW_CONFIG_PDA_SEED, authority_and_owner.authority.key().as_ref()],
+		bump = escrow_config.bump,
+	)]
+	pub escrow_config: Account<'info, EscrowConfig>,
+
+	// The arbiter stored in the escrow configuration must sign
+	#[account(address = escrow_config.arbiter @ QBitFlowError::Unauthorized)]
+	pub arbiter: Signer<'info>,
+
+	#[account(
+		mut,
+		seeds = [ESCROW_PDA_SEED, uuid.as_ref()],
+		bump = escrow.bump,
+		has_one = merchant,
+		has_one = organization,
+		has_one = mint @ QBitFlowError::InvalidTokenAccounts,
+	)]
+	pub escrow: Account<'info, Escrow>,
+
+	#[account(
+		mut,
+		seeds = [ESCROW_VAULT_PDA_SEED, uuid.as_ref()],
+		bump
+	)]
+	pub vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+	// ATA of the payer, receiving the part of the escrow awarded to the payer
+	#[account(
+		mut,
+		associated_token::mint = mint,
+		associated_token::authority = escrow.payer,
+		associated_token::token_program = token_program
+	)]
+	pub payer_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+	/// CHECK: The merchant of the escrow (verified against the escrow)
+	pub merchant: UncheckedAccount<'info>,
+
+	#[account(
+		init_if_needed,
+		payer = authority_and_owner.owner, // authority pays for token account initialization (merchant)
+		associated_token::mint = mint,
+		associated_token::authority = merchant,
+		associated_token::token_program = token_program
+	)]
+	pub merchant_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+	#[account(
+		init_if_needed,
+		payer = authority_and_owner.owner,
+		associated_token::mint = mint,
+		associated_token::authority = authority_and_owner.owner, // fee recipient is authority owner
+		associated_token::token_program = token_program
+	)]
+	pub fee_recipient_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+	/// CHECK: The organization of the escrow (verified against the escrow)
+	pub organization: UncheckedAccount<'info>,
+
+	#[account(
+		init_if_needed,
+		payer = authority_and_owner.owner, // authority pays for token account initialization (organization fee recipient)
+		associated_token::mint = mint,
+		associated_token::authority = organization,
+		associated_token::token_program = token_program
+	)]
+	pub organization_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+	// Writable so the transfer fees withheld in the vault can be harvested before closing it
+	#[account(mut)]
+	pub mint: Box<InterfaceAccount<'info, Mint>>,
+
+	pub system_program: Program<'info, System>,
+	pub token_program: Interface<'info, TokenInterface>,
+	pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+
+/**
+ * Create an escrow payment
+ * The funds are moved from the payer to a vault owned by the authority PDA
+ * The fees are fixed at creation, and applied when the funds are released to the merchant
+ * The payer can raise a dispute until release_at
+ * Emits an EscrowCreated event
+ */
+#[allow(clippy::too_many_arguments)] // Mirrors the client-facing instruction arguments
+pub fn create_escrow_payment<'info>(
+	ctx: Context<'_, '_, '_, 'info, CreateEscrowPayment<'info>>,
+	uuid: [u8; 16],
+	amount: u64,
+	fee_bps: u16,
+	organization_fee_bps: u16,
+	release_at: i64,
+	compute_refund_params: ComputeRefundData,
+	transfer_fee_policy: TransferFeePolicy,
+) -> Result<()> {
+	// Validate the fees now, so the release cannot fail later because of them
+	calculate_fee(amount, fee_bps, organization_fee_bps)?;
+
+	if release_at <= Clock::get()?.unix_timestamp {
+		return err!(QBitFlowError::InvalidExpiration);
+	}
+
+	// Move the funds to the vault. The amount held is the amount that actually lands in the vault (after the Token-2022 transfer fee, if any)
+	let escrow_transfer = TransferAmounts::compute(&ctx.accounts.mint.to_account_info(), amount, transfer_fee_policy)?;
+
+	transfer_checked_with_hook(
+		CpiContext::new(
+			ctx.accounts.token_program.to_account_info(),
+			TransferChecked {
+				from: ctx.accounts.payer_token_account.to_account_info(),
+				mint: ctx.accounts.mint.to_account_info(),
+				to: ctx.accounts.vault.to_account_info(),
+				authority: ctx.accounts.payer.to_account_info(),
+			},
+		).with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+		escrow_transfer.gross,
+		ctx.accounts.mint.decimals,
+	)?;
+
+	// Compute refund in tokens for the authority owner to refund the compute cost paid in SOL
+	let _ = compute_refund(uuid, 0, compute_refund_params, ctx.accounts.mint.decimals, CpiContext::new(
+		ctx.accounts.token_program.to_account_info(),
+		TransferChecked {
+			from: ctx.accounts.payer_token_account.to_account_info(),
+			mint: ctx.accounts.mint.to_account_info(),
+			to: ctx.accounts.fee_recipient_token_account.to_account_info(),
+			authority: ctx.accounts.payer.to_account_info(),
+		},
+	).with_remaining_accounts(ctx.remaining_accounts.to_vec()));
+
+	ctx.accounts.escrow.set_inner(Escrow {
+		payer: ctx.accounts.payer.key(),
+		merchant: ctx.accounts.merchant.key(),
+		organization: ctx.accounts.organization.key(),
+		mint: ctx.accounts.mint.key(),
+		amount: escrow_transfer.net,
+		fee_bps,
+		organization_fee_bps,
+		release_at,
+		disputed: false,
+		bump: ctx.bumps.escrow,
+	});
+
+	emit!(EscrowCreated {
+		uuid,
+		amount: escrow_transfer.net,
+		release_at,
+	});
+
+	Ok(())
+}
+
+
+/**
+ * Release an escrow payment to the merchant (minus fees)
+ * Before release_at, only the payer can release it. After, the authority owner can release it alone
+ * A disputed escrow can only be settled by the arbiter
+ * The escrow and vault are closed (rent returned to the authority owner)
+ * Emits an EscrowReleased event
+ */
+pub fn release_escrow<'info>(
+	ctx: Context<'_, '_, '_, 'info, ReleaseEscrow<'info>>,
+	uuid: [u8; 16],
+) -> Result<()> {
+	let escrow = &ctx.accounts.escrow;
+
+	if escrow.disputed {
+		return err!(QBitFlowError::EscrowDisputed);
+	}
+
+	if ctx.accounts.payer.is_none() && Clock::get()?.unix_timestamp < escrow.release_at {
+		return err!(QBitFlowError::EscrowLocked);
+	}
+
+	let amount = escrow.amount;
+
+	let accounts = &ctx.accounts;
+	let (merchant_amount, fee_amount, organization_fee_amount) = pay_out_escrow(
+		&accounts.token_program,
+		&accounts.vault,
+		&accounts.mint,
+		&accounts.authority_and_owner.authority,
+		ctx.remaining_accounts,
+		accounts.merchant_token_account.to_account_info(),
+		accounts.fee_recipient_token_account.to_account_info(),
+		accounts.organization_token_account.to_account_info(),
+		escrow,
+		amount,
+	)?;
+
+	// Close the vault and the escrow, rent goes back to the authority owner (who paid for them)
+	close_vault(&accounts.token_program, &accounts.vault, &accounts.mint, accounts.authority_and_owner.owner.to_account_info(), &accounts.authority_and_owner.authority)?;
+	ctx.accounts.escrow.close(ctx.accounts.authority_and_owner.owner.to_account_info())?;
+
+	emit!(EscrowReleased {
+		uuid,
+		amount,
+		merchant_amount,
+		fee_amount,
+		organization_fee_amount,
+	});
+
+	Ok(())
+}
+
+
+/**
+ * Raise a dispute on an escrow payment (payer only, before release_at)
+ * Once disputed, the funds can only be settled by the arbiter
+ * Emits an EscrowDisputeRaised event
+ */
+pub fn dispute_escrow(
+	ctx: Context<DisputeEscrow>,
+	uuid: [u8; 16],
+) -> Result<()> {
+	let escrow = &mut ctx.accounts.escrow;
+
+	if escrow.disputed {
+		return err!(QBitFlowError::EscrowDisputed);
+	}
+
+	if Clock::get()?.unix_timestamp >= escrow.release_at {
+		return err!(QBitFlowError::DisputeWindowClosed);
+	}
+
+	escrow.disputed = true;
+
+	emit!(EscrowDisputeRaised { uuid });
+
+	Ok(())
+}
+
+
+/**
+ * Resolve a disputed escrow payment (arbiter only)
+ * `payer_amount` is returned to the payer (without fees), the rest is released to the merchant (minus fees)
+ * The escrow and vault are closed (rent returned to the authority owner)
+ * Emits an EscrowDisputeResolved event
+ */
+pub fn resolve_escrow_dispute<'info>(
+	ctx: Context<'_, '_, '_, 'info, ResolveEscrowDispute<'info>>,
+	uuid: [u8; 16],
+	payer_amount: u64,
+) -> Result<()> {
+	let escrow = &ctx.accounts.escrow;
+
+	if !escrow.disputed {
+		return err!(QBitFlowError::EscrowNotDisputed);
+	}
+
+	let merchant_share = escrow.merchant_share(payer_amount)?;
+
+	let accounts = &ctx.accounts;
+
+	// Return the payer's share
+	transfer_from_vault(&accounts.token_program, &accounts.vault, &accounts.mint, accounts.payer_token_account.to_account_info(), &accounts.authority_and_owner.authority, ctx.remaining_accounts, payer_amount)?;
+
+	// Release the merchant's share (minus fees)
+	let (merchant_amount, fee_amount, organization_fee_amount) = pay_out_escrow(
+		&accounts.token_program,
+		&accounts.vault,
+		&accounts.mint,
+		&accounts.authority_and_owner.authority,
+		ctx.remaining_accounts,
+		accounts.merchant_token_account.to_account_info(),
+		accounts.fee_recipient_token_account.to_account_info(),
+		accounts.organization_token_account.to_account_info(),
+		escrow,
+		merchant_share,
+	)?;
+
+	// Close the vault and the escrow, rent goes back to the authority owner (who paid for them)
+	close_vault(&accounts.token_program, &accounts.vault, &accounts.mint, accounts.authority_and_owner.owner.to_account_info(), &accounts.authority_and_owner.authority)?;
+	ctx.accounts.escrow.close(ctx.accounts.authority_and_owner.owner.to_account_info())?;
+
+	emit!(EscrowDisputeResolved {
+		uuid,
+		payer_amount,
+		merchant_amount,
+		fee_amount,
+		organization_fee_amount,
+	});
+
+	Ok(())
+}
+
+
+// Release `amount` from an escrow vault to the merchant, the fee recipient and the organization (fees of the escrow, split by calculate_fee)
+// The funds are already in the vault, so the recipients absorb the Token-2022 transfer fee (if any)
+// Returns the net amounts received by the merchant, the fee recipient and the organization
+#[inline(never)]
+#[allow(clippy::too_many_arguments)]
+fn pay_out_escrow<'info>(
+	token_program: &Interface<'info, TokenInterface>,
+	vault: &InterfaceAccount<'info, TokenAccount>,
+	mint: &InterfaceAccount<'info, Mint>,
+	authority: &Account<'info, Authority>,
+	remaining_accounts: &[AccountInfo<'info>],
+	merchant_token_account: AccountInfo<'info>,
+	fee_recipient_token_account: AccountInfo<'info>,
+	organization_token_account: AccountInfo<'info>,
+	escrow: &Escrow,
+	amount: u64,
+) -> Result<(u64, u64, u64)> {
+	if amount == 0 {
+		return Ok((0, 0, 0));
+	}
+
+	let (fee_amount, org_fee_amount, remaining_amount) = escrow.split_release(amount)?;
+
+	let mint_info = mint.to_account_info();
+	let fee_transfer = TransferAmounts::compute(&mint_info, fee_amount, TransferFeePolicy::RecipientsAbsorb)?;
+	let org_fee_transfer = TransferAmounts::compute(&mint_info, org_fee_amount, TransferFeePolicy::RecipientsAbsorb)?;
+	let merchant_transfer = TransferAmounts::compute(&mint_info, remaining_amount, TransferFeePolicy::RecipientsAbsorb)?;
+
+	transfer_from_vault(token_program, vault, mint, fee_recipient_token_account, authority, remaining_accounts, fee_transfer.gross)?;
+	transfer_from_vault(token_program, vault, mint, organization_token_account, authority, remaining_accounts, org_fee_transfer.gross)?;
+	transfer_from_vault(token_program, vault, mint, merchant_token_account, authority, remaining_accounts, merchant_transfer.gross)?;
+
+	Ok((merchant_transfer.net, fee_transfer.net, org_fee_transfer.net))
+}
@@ -1,6 +1,6 @@
-use anchor_spl::token::{Token, TokenAccount};
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
 use anchor_lang::{prelude::*, solana_program::program_option::COption};
-use crate::{errors::QBitFlowError, instructions::permit::PermitRegistry, state::Authority, AUTHORITY_PDA_SEED, PERMIT_REGISTRY_PDA_SEED};
+use crate::{errors::QBitFlowError, instructions::permit::PermitRegistry, state::{Authority, EscrowConfig}, AUTHORITY_PDA_SEED, ESCROW_CONFIG_PDA_SEED, PERMIT_REGISTRY_PDA_SEED};
 
 #[derive(Accounts)]
 pub struct Initialize<'info> {
@@ -53,6 +53,42 @@ pub fn update_owner(ctx: Context<UpdateOwner>, new_owner: Pubkey) -> Result<()>
 }
 
 
+#[derive(Accounts)]
+pub struct SetArbiter<'info> {
+	#[account(
+		seeds = [AUTHORITY_PDA_SEED],
+		bump = authority.bump,
+		has_one = owner @ QBitFlowError::Unauthorized, // Ensure the signer is the current owner
+		has_one = co_signer @ QBitFlowError::Unauthorized,
+	)]
+	pub authority: Account<'info, Authority>,
+
+	// Escrow configuration, created on the first call (the owner pays for it)
+	#[account(
+		init_if_needed,
+		payer = owner,
+		space = EscrowConfig::LEN,
+		seeds = [ESCROW_CONFIG_PDA_SEED, authority.key().as_ref()],
+		bump
+	)]
+	pub escrow_config: Account<'info, EscrowConfig>,
+
+	#[account(mut, address = authority.owner)]
+	pub owner: Signer<'info>, // current owner must sign
+
+	pub co_signer: Signer<'info>,  // Co-signer must sign (no account needed!)
+
+	pub system_program: Program<'info, System>,
+}
+
+pub fn set_arbiter(ctx: Context<SetArbiter>, new_arbiter: Pubkey) -> Result<()> {
+	let escrow_config = &mut ctx.accounts.escrow_config;
+	escrow_config.arbiter = new_arbiter;
+	escrow_config.bump = ctx.bumps.escrow_config;
+	Ok(())
+}
+
+
 #[derive(Accounts)]
 pub struct SetDelegate<'info> {
 	#[account(
@@ -82,14 +118,15 @@ pub struct SetDelegate<'info> {
     #[account(
 		mut,
 		associated_token::mint = mint,
-		associated_token::authority = subscriber
+		associated_token::authority = subscriber,
+		associated_token::token_program = token_program
 	)]
-    pub subscriber_token_account: Account<'info, TokenAccount>,
+    pub subscriber_token_account: InterfaceAccount<'info, TokenAccount>,
 
-	// The token mint used for this subscription
-    pub mint: Account<'info, anchor_spl::token::Mint>,
+	// The token mint used for this subscription (Token or Token-2022)
+    pub mint: InterfaceAccount<'info, Mint>,
 
-	pub token_program: Program<'info, Token>,
+	pub token_program: Interface<'info, TokenInterface>,
 }
 
 
@@ -105,7 +142,8 @@ pub fn set_delegate(ctx: Context<SetDelegate>) -> Result<()> {
 				&ctx.accounts.token_program,
 				&ctx.accounts.subscriber,
 				&ctx.accounts.authority,
-				&ctx.accounts.subscriber_token_account
+				&ctx.accounts.subscriber_token_account,
+				&ctx.accounts.mint
 			)?;
 		}
 		COption::Some(current_delegate) => {
@@ -118,7 +156,8 @@ pub fn set_delegate(ctx: Context<SetDelegate>) -> Result<()> {
 				&ctx.accounts.token_program,
 				&ctx.accounts.subscriber,
 				&ctx.accounts.authority,
-				&ctx.accounts.subscriber_token_account
+				&ctx.accounts.subscriber_token_account,
+				&ctx.accounts.mint
 			)?;
 			}
 		}
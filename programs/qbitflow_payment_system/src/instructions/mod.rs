@@ -1,9 +1,19 @@
 pub mod initialize;
 pub mod payments;
+pub mod refunds;
+pub mod authorizations;
+pub mod escrow;
+pub mod plans;
 pub mod subscriptions;
 pub mod permit;
 pub mod compute_refund;
+pub mod transfer_fee;
+pub mod transfer_hook;
 
 pub use initialize::*;
 pub use payments::*;
+pub use refunds::*;
+pub use authorizations::*;
+pub use escrow::*;
+pub use plans::*;
 pub use subscriptions::*;
\ No newline at end of file
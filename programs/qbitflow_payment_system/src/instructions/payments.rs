@@ -1,12 +1,14 @@
 use anchor_lang::prelude::*;
 use anchor_lang::system_program;
 use anchor_spl::associated_token::AssociatedToken;
-use anchor_spl::token::Mint;
-use anchor_spl::token::{self, Token, TokenAccount, Transfer};
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface, TransferChecked};
 use crate::instructions::compute_refund::compute_refund;
 use crate::instructions::compute_refund::ComputeRefundData;
+use crate::instructions::transfer_fee::{TransferAmounts, TransferFeePolicy};
+use crate::instructions::transfer_hook::transfer_checked_with_hook;
 use crate::state::*;
 use crate::errors::*;
+use crate::{FEE_DENOMINATOR, MAX_SPLIT_RECIPIENTS, PAYMENT_RECEIPT_PDA_SEED};
 
 #[derive(Accounts)]
 #[instruction(amount: u64, fee_bps: u16, uuid: [u8; 16], organization_fee_bps: u16)]
@@ -29,6 +31,17 @@ pub struct ProcessSolPayment<'info> {
     /// CHECK: This is the organization fee recipient (optional). If provided, must be initialized
     #[account(mut)]
     pub organization_fee_recipient: Option<UncheckedAccount<'info>>,
+
+	// Optional receipt of the payment, derived from its uuid
+	// If provided, the payment is recorded on-chain, and any other payment with the same uuid fails (the account already exists)
+	#[account(
+		init,
+		payer = authority_and_owner.owner, // authority owner pays for the receipt account creation
+		space = PaymentReceipt::LEN,
+		seeds = [PAYMENT_RECEIPT_PDA_SEED, uuid.as_ref()],
+		bump
+	)]
+	pub payment_receipt: Option<Account<'info, PaymentReceipt>>,
     
     pub system_program: Program<'info, System>,
 }
@@ -49,9 +62,10 @@ pub struct ProcessTokenPayment<'info> {
 		// Automatically sets the address to the associated token account for (payer, mint)
 		// No need to pass the address from the client side (and even though we do, it will be ignored)
 		associated_token::mint = mint,
-		associated_token::authority = payer
+		associated_token::authority = payer,
+		associated_token::token_program = token_program
 	)]
-    pub payer_token_account: Account<'info, TokenAccount>,
+    pub payer_token_account: InterfaceAccount<'info, TokenAccount>,
     
 
 	/// CHECK: The merchant receiving the payments for this subscription (main account). Must be initialized
@@ -63,9 +77,10 @@ pub struct ProcessTokenPayment<'info> {
 		init_if_needed,
 		payer = authority_and_owner.owner, // authority pays for token account initialization (merchant)
 		associated_token::mint = mint,
-		associated_token::authority = merchant
+		associated_token::authority = merchant,
+		associated_token::token_program = token_program
 	)]
-    pub merchant_token_account: Account<'info, TokenAccount>,
+    pub merchant_token_account: InterfaceAccount<'info, TokenAccount>,
     
     // Ensures this token account belongs to authority.owner
 	// No need to pass the address from the client side (and even though we do, it will be ignored)
@@ -73,9 +88,10 @@ pub struct ProcessTokenPayment<'info> {
         init_if_needed,
 		payer = authority_and_owner.owner,
 		associated_token::mint = mint,
-		associated_token::authority = authority_and_owner.owner // fee recipient is authority owner
+		associated_token::authority = authority_and_owner.owner, // fee recipient is authority owner
+		associated_token::token_program = token_program
     )]
-    pub fee_recipient_token_account: Account<'info, TokenAccount>,
+    pub fee_recipient_token_account: InterfaceAccount<'info, TokenAccount>,
 
 	/// CHECK: This is the organization receiving a portion of the fees (optional). If provided, must be initialized
 	#[account(mut)]
@@ -85,16 +101,95 @@ pub struct ProcessTokenPayment<'info> {
 		init_if_needed,
 		payer = authority_and_owner.owner, // authority pays for token account initialization (organization fee recipient)
 		associated_token::mint = mint,
-		associated_token::authority = organization
+		associated_token::authority = organization,
+		associated_token::token_program = token_program
 	)]
-    pub organization_token_account: Account<'info, TokenAccount>,
+    pub organization_token_account: InterfaceAccount<'info, TokenAccount>,
     
-	// The mint of the token being transferred
+	// The mint of the token being transferred (Token or Token-2022)
 	// #[account()]
-	pub mint: Account<'info, Mint>,
+	pub mint: InterfaceAccount<'info, Mint>,
+
+	// Optional receipt of the payment, derived from its uuid
+	// If provided, the payment is recorded on-chain, and any other payment with the same uuid fails (the account already exists)
+	#[account(
+		init,
+		payer = authority_and_owner.owner, // authority owner pays for the receipt account creation
+		space = PaymentReceipt::LEN,
+		seeds = [PAYMENT_RECEIPT_PDA_SEED, uuid.as_ref()],
+		bump
+	)]
+	pub payment_receipt: Option<Account<'info, PaymentReceipt>>,
 	
     pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
+    pub token_program: Interface<'info, TokenInterface>,
+	pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+// Share of a split payment
+// Bps shares are computed on what remains of the net amount (after protocol and organization fees) once the fixed shares are paid
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub enum SplitShareAmount {
+	Bps(u16),
+	Fixed(u64),
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SplitShare {
+	pub recipient: Pubkey, // Owner of the recipient token account
+	pub share: SplitShareAmount,
+}
+
+#[derive(Accounts)]
+#[instruction(amount: u64, fee_bps: u16, uuid: [u8; 16], organization_fee_bps: u16)]
+pub struct ProcessSplitPayment<'info> {
+    #[account()]
+	pub authority_and_owner: AuthorityAndOwner<'info>,
+
+	// The payer is the one initiating the payment
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+	// ATA of the payer
+    #[account(
+		mut,
+		associated_token::mint = mint,
+		associated_token::authority = payer,
+		associated_token::token_program = token_program
+	)]
+    pub payer_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    // Ensures this token account belongs to authority.owner
+    #[account(
+        init_if_needed,
+		payer = authority_and_owner.owner,
+		associated_token::mint = mint,
+		associated_token::authority = authority_and_owner.owner, // fee recipient is authority owner
+		associated_token::token_program = token_program
+    )]
+    pub fee_recipient_token_account: InterfaceAccount<'info, TokenAccount>,
+
+	/// CHECK: This is the organization receiving a portion of the fees. Must be initialized
+	#[account(mut)]
+	pub organization: UncheckedAccount<'info>,
+
+    #[account(
+		init_if_needed,
+		payer = authority_and_owner.owner, // authority pays for token account initialization (organization fee recipient)
+		associated_token::mint = mint,
+		associated_token::authority = organization,
+		associated_token::token_program = token_program
+	)]
+    pub organization_token_account: InterfaceAccount<'info, TokenAccount>,
+
+	// The mint of the token being transferred (Token or Token-2022)
+	pub mint: InterfaceAccount<'info, Mint>,
+
+	// The recipients' token accounts are passed as the first remaining accounts (one per share, in the same order, must be initialized)
+	// Any following remaining accounts are forwarded to the token transfers (transfer hook extra accounts)
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
 	pub associated_token_program: Program<'info, AssociatedToken>,
 }
 
@@ -130,18 +225,19 @@ pub fn process_sol_payment(
 
 
     // Transfer organization fee if applicable
-    if org_fee_amount > 0 && ctx.accounts.organization_fee_recipient.is_some() {
-        system_program::transfer(
-            CpiContext::new(
-                ctx.accounts.system_program.to_account_info(),
-                system_program::Transfer {
-                    from: ctx.accounts.payer.to_account_info(),
-                    to: ctx.accounts.organization_fee_recipient.as_ref().unwrap().to_account_info(),
-                },
-            ),
-            org_fee_amount,
-        )?;
-
+    if org_fee_amount > 0 {
+        if let Some(organization_fee_recipient) = &ctx.accounts.organization_fee_recipient {
+            system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    system_program::Transfer {
+                        from: ctx.accounts.payer.to_account_info(),
+                        to: organization_fee_recipient.to_account_info(),
+                    },
+                ),
+                org_fee_amount,
+            )?;
+        }
     }
 
     // Transfer remaining amount to merchant
@@ -156,26 +252,47 @@ pub fn process_sol_payment(
         remaining_amount,
     )?;
 
+	let organization_fee_amount = if ctx.accounts.organization_fee_recipient.is_some() { org_fee_amount } else { 0 };
+
+	// Record the payment if a receipt is provided
+	if let Some(payment_receipt) = &mut ctx.accounts.payment_receipt {
+		payment_receipt.set_inner(PaymentReceipt {
+			payer: ctx.accounts.payer.key(),
+			merchant: ctx.accounts.merchant.key(),
+			mint: None,
+			amount,
+			fee_amount,
+			organization_fee_amount,
+			merchant_amount: remaining_amount,
+			refunded_amount: 0,
+			timestamp: Clock::get()?.unix_timestamp,
+			bump: ctx.bumps.payment_receipt.unwrap_or_default(),
+		});
+	}
+
 
     emit!(PaymentProcessed {
         uuid,
 		// from: ctx.accounts.payer.key(),
         // to: ctx.accounts.merchant.key(),
-        // amount: remaining_amount,
         // token_address: None, // Add this line to avoid errors
-
+        amount,
+        merchant_amount: remaining_amount,
+        fee_amount,
+        organization_fee_amount,
     });
 
     Ok(())
 }
 
-pub fn process_token_payment(
-    ctx: Context<ProcessTokenPayment>,
+pub fn process_token_payment<'info>(
+    ctx: Context<'_, '_, '_, 'info, ProcessTokenPayment<'info>>,
     amount: u64,
     fee_bps: u16,
     uuid: [u8; 16],
     organization_fee_bps: u16,
 	compute_refund_params: ComputeRefundData,
+	transfer_fee_policy: TransferFeePolicy,
 ) -> Result<()> {
     if amount == 0 {
         return err!(QBitFlowError::ZeroAmount);
@@ -187,67 +304,353 @@ pub fn process_token_payment(
         .and_then(|x| x.checked_sub(org_fee_amount))
         .ok_or(QBitFlowError::Overflow)?;
 
+	// Apply the Token-2022 transfer fee (if any) to each transfer, according to the policy
+	let mint_info = ctx.accounts.mint.to_account_info();
+	let fee_transfer = TransferAmounts::compute(&mint_info, fee_amount, transfer_fee_policy)?;
+	let org_fee_transfer = TransferAmounts::compute(&mint_info, org_fee_amount, transfer_fee_policy)?;
+	let merchant_transfer = TransferAmounts::compute(&mint_info, remaining_amount, transfer_fee_policy)?;
+
 
     // Transfer fee to fee recipient
-    token::transfer(
+    transfer_checked_with_hook(
         CpiContext::new(
             ctx.accounts.token_program.to_account_info(),
-            Transfer {
+            TransferChecked {
                 from: ctx.accounts.payer_token_account.to_account_info(),
+                mint: ctx.accounts.mint.to_account_info(),
                 to: ctx.accounts.fee_recipient_token_account.to_account_info(),
                 authority: ctx.accounts.payer.to_account_info(),
             },
-        ),
-        fee_amount,
+        ).with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+        fee_transfer.gross,
+        ctx.accounts.mint.decimals,
     )?;
-													
+
 
     // Transfer organization fee if applicable
     if org_fee_amount > 0 {
-        token::transfer(
+        transfer_checked_with_hook(
             CpiContext::new(
                 ctx.accounts.token_program.to_account_info(),
-                Transfer {
+                TransferChecked {
                     from: ctx.accounts.payer_token_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
                     to: ctx.accounts.organization_token_account.to_account_info(),
                     authority: ctx.accounts.payer.to_account_info(),
                 },
-            ),
-            org_fee_amount,
+            ).with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+            org_fee_transfer.gross,
+            ctx.accounts.mint.decimals,
         )?;
     }
 
 
     // Transfer remaining amount to merchant
-    token::transfer(
+    transfer_checked_with_hook(
         CpiContext::new(
             ctx.accounts.token_program.to_account_info(),
-            Transfer {
+            TransferChecked {
                 from: ctx.accounts.payer_token_account.to_account_info(),
+                mint: ctx.accounts.mint.to_account_info(),
                 to: ctx.accounts.merchant_token_account.to_account_info(),
                 authority: ctx.accounts.payer.to_account_info(),
             },
-        ),
-        remaining_amount,
+        ).with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+        merchant_transfer.gross,
+        ctx.accounts.mint.decimals,
     )?;
 
 	// Compute refund in tokens for the authority owner to refund the compute cost paid in SOL
-	let _ = compute_refund(uuid, 0, compute_refund_params, CpiContext::new(
+	let _ = compute_refund(uuid, 0, compute_refund_params, ctx.accounts.mint.decimals, CpiContext::new(
 		ctx.accounts.token_program.to_account_info(),
-		Transfer {
+		TransferChecked {
 			from: ctx.accounts.payer_token_account.to_account_info(),
+			mint: ctx.accounts.mint.to_account_info(),
 			to: ctx.accounts.fee_recipient_token_account.to_account_info(),
 			authority: ctx.accounts.payer.to_account_info(),
 		},
-	));
+	).with_remaining_accounts(ctx.remaining_accounts.to_vec()));
+
+	// Record the payment if a receipt is provided (amounts are the ones actually received by each party)
+	if let Some(payment_receipt) = &mut ctx.accounts.payment_receipt {
+		payment_receipt.set_inner(PaymentReceipt {
+			payer: ctx.accounts.payer.key(),
+			merchant: ctx.accounts.merchant.key(),
+			mint: Some(ctx.accounts.mint.key()),
+			amount,
+			fee_amount: fee_transfer.net,
+			organization_fee_amount: org_fee_transfer.net,
+			merchant_amount: merchant_transfer.net,
+			refunded_amount: 0,
+			timestamp: Clock::get()?.unix_timestamp,
+			bump: ctx.bumps.payment_receipt.unwrap_or_default(),
+		});
+	}
 
     emit!(PaymentProcessed {
         uuid,
         // from: ctx.accounts.payer.key(),
         // to: ctx.accounts.merchant_token_account.owner,
         // token_address: Some(ctx.accounts.payer_token_account.mint),
-        // amount: remaining_amount,
+        amount,
+        // Net amounts actually received by each party (after the Token-2022 transfer fee, if any)
+        merchant_amount: merchant_transfer.net,
+        fee_amount: fee_transfer.net,
+        organization_fee_amount: org_fee_transfer.net,
     });
 
     Ok(())
-}
\ No newline at end of file
+}
+
+
+/**
+ * Process a one-time payment in SPL tokens split between several recipients
+ * The protocol and organization fees are applied once on the whole amount, then the net amount is split according to the shares
+ * The shares must sum exactly to the net amount: fixed shares are paid first, and bps shares (which must total 100%) split the rest
+ * Emits a SplitPaymentProcessed event with the net amount received by each recipient
+ */
+#[allow(clippy::too_many_arguments)] // Mirrors the client-facing instruction arguments
+pub fn process_split_payment<'info>(
+    ctx: Context<'_, '_, '_, 'info, ProcessSplitPayment<'info>>,
+    amount: u64,
+    fee_bps: u16,
+    uuid: [u8; 16],
+    organization_fee_bps: u16,
+	shares: Vec<SplitShare>,
+	compute_refund_params: ComputeRefundData,
+	transfer_fee_policy: TransferFeePolicy,
+) -> Result<()> {
+    if amount == 0 {
+        return err!(QBitFlowError::ZeroAmount);
+    }
+
+	if shares.is_empty() || shares.len() > MAX_SPLIT_RECIPIENTS || ctx.remaining_accounts.len() < shares.len() {
+		return err!(QBitFlowError::InvalidSplitShares);
+	}
+
+    let (fee_amount, org_fee_amount) = calculate_fee(amount, fee_bps, organization_fee_bps)?;
+    let net_amount = amount
+        .checked_sub(fee_amount)
+        .and_then(|x| x.checked_sub(org_fee_amount))
+        .ok_or(QBitFlowError::Overflow)?;
+
+	let share_amounts = compute_split_amounts(net_amount, &shares)?;
+
+	// The first remaining accounts are the recipients' token accounts, the others are forwarded to the transfers (transfer hook)
+	let (recipient_token_accounts, hook_accounts) = ctx.remaining_accounts.split_at(shares.len());
+
+	// Apply the Token-2022 transfer fee (if any) to each transfer, according to the policy
+	let mint_info = ctx.accounts.mint.to_account_info();
+	let fee_transfer = TransferAmounts::compute(&mint_info, fee_amount, transfer_fee_policy)?;
+	let org_fee_transfer = TransferAmounts::compute(&mint_info, org_fee_amount, transfer_fee_policy)?;
+
+    // Transfer fee to fee recipient
+    transfer_checked_with_hook(
+        CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: ctx.accounts.payer_token_account.to_account_info(),
+                mint: ctx.accounts.mint.to_account_info(),
+                to: ctx.accounts.fee_recipient_token_account.to_account_info(),
+                authority: ctx.accounts.payer.to_account_info(),
+            },
+        ).with_remaining_accounts(hook_accounts.to_vec()),
+        fee_transfer.gross,
+        ctx.accounts.mint.decimals,
+    )?;
+
+    // Transfer organization fee if applicable
+    if org_fee_amount > 0 {
+        transfer_checked_with_hook(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.payer_token_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.organization_token_account.to_account_info(),
+                    authority: ctx.accounts.payer.to_account_info(),
+                },
+            ).with_remaining_accounts(hook_accounts.to_vec()),
+            org_fee_transfer.gross,
+            ctx.accounts.mint.decimals,
+        )?;
+    }
+
+	// Pay each recipient its share
+	let mut payouts = Vec::with_capacity(shares.len());
+	for ((share, share_amount), recipient_token_account) in shares.iter().zip(share_amounts).zip(recipient_token_accounts) {
+		// Ensure the token account belongs to the recipient of the share, and holds the right mint
+		if recipient_token_account.owner != ctx.accounts.token_program.key {
+			return err!(QBitFlowError::InvalidTokenAccounts);
+		}
+		let token_account = TokenAccount::try_deserialize(&mut &recipient_token_account.try_borrow_data()?[..])?;
+		if token_account.owner != share.recipient || token_account.mint != ctx.accounts.mint.key() {
+			return err!(QBitFlowError::InvalidTokenAccounts);
+		}
+
+		let share_transfer = TransferAmounts::compute(&mint_info, share_amount, transfer_fee_policy)?;
+		if share_transfer.gross > 0 {
+			transfer_checked_with_hook(
+				CpiContext::new(
+					ctx.accounts.token_program.to_account_info(),
+					TransferChecked {
+						from: ctx.accounts.payer_token_account.to_account_info(),
+						mint: ctx.accounts.mint.to_account_info(),
+						to: recipient_token_account.clone(),
+						authority: ctx.accounts.payer.to_account_info(),
+					},
+				).with_remaining_accounts(hook_accounts.to_vec()),
+				share_transfer.gross,
+				ctx.accounts.mint.decimals,
+			)?;
+		}
+
+		payouts.push(SplitPayout {
+			recipient: share.recipient,
+			amount: share_transfer.net,
+		});
+	}
+
+	// Compute refund in tokens for the authority owner to refund the compute cost paid in SOL
+	let _ = compute_refund(uuid, 0, compute_refund_params, ctx.accounts.mint.decimals, CpiContext::new(
+		ctx.accounts.token_program.to_account_info(),
+		TransferChecked {
+			from: ctx.accounts.payer_token_account.to_account_info(),
+			mint: ctx.accounts.mint.to_account_info(),
+			to: ctx.accounts.fee_recipient_token_account.to_account_info(),
+			authority: ctx.accounts.payer.to_account_info(),
+		},
+	).with_remaining_accounts(hook_accounts.to_vec()));
+
+	emit!(SplitPaymentProcessed {
+		uuid,
+		amount,
+		fee_amount: fee_transfer.net,
+		organization_fee_amount: org_fee_transfer.net,
+		payouts,
+	});
+
+    Ok(())
+}
+
+
+// Compute the amount of each share of a split payment
+// Fixed shares are paid as is, bps shares split what remains (they must total FEE_DENOMINATOR, the rounding dust goes to the last bps share)
+// Fails unless the shares sum exactly to the net amount
+fn compute_split_amounts(net_amount: u64, shares: &[SplitShare]) -> Result<Vec<u64>> {
+	let mut fixed_total: u64 = 0;
+	let mut bps_total: u32 = 0;
+	for share in shares {
+		match share.share {
+			SplitShareAmount::Fixed(fixed) => fixed_total = fixed_total.checked_add(fixed).ok_or(QBitFlowError::Overflow)?,
+			// A 0 bps share would receive the whole rounding dust (everything left) if it is the last one
+			SplitShareAmount::Bps(0) => return err!(QBitFlowError::InvalidSplitShares),
+			SplitShareAmount::Bps(bps) => bps_total += bps as u32,
+		}
+	}
+
+	let bps_base = net_amount.checked_sub(fixed_total).ok_or(QBitFlowError::InvalidSplitShares)?;
+
+	let last_bps_index = shares.iter().rposition(|share| matches!(share.share, SplitShareAmount::Bps(_)));
+
+	// As soon as a bps share is present, the bps shares must split the whole remainder
+	if last_bps_index.is_some() && bps_total != FEE_DENOMINATOR as u32 {
+		return err!(QBitFlowError::InvalidSplitShares);
+	}
+
+	let mut amounts = Vec::with_capacity(shares.len());
+	let mut bps_distributed: u64 = 0;
+	for (index, share) in shares.iter().enumerate() {
+		let share_amount = match share.share {
+			SplitShareAmount::Fixed(fixed) => fixed,
+			SplitShareAmount::Bps(_) if Some(index) == last_bps_index => bps_base - bps_distributed,
+			SplitShareAmount::Bps(bps) => {
+				let share_amount = (bps_base as u128 * bps as u128 / FEE_DENOMINATOR as u128) as u64;
+				bps_distributed += share_amount;
+				share_amount
+			}
+		};
+		amounts.push(share_amount);
+	}
+
+	// The shares must sum exactly to the net amount
+	let total = amounts.iter().try_fold(0u64, |total, share_amount| total.checked_add(*share_amount)).ok_or(QBitFlowError::Overflow)?;
+	if total != net_amount {
+		return err!(QBitFlowError::InvalidSplitShares);
+	}
+
+	Ok(amounts)
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn share(share: SplitShareAmount) -> SplitShare {
+		SplitShare { recipient: Pubkey::new_unique(), share }
+	}
+
+	fn invalid_split() -> anchor_lang::error::Error {
+		QBitFlowError::InvalidSplitShares.into()
+	}
+
+	#[test]
+	fn fixed_shares() {
+		let shares = [share(SplitShareAmount::Fixed(60)), share(SplitShareAmount::Fixed(40))];
+		assert_eq!(compute_split_amounts(100, &shares).unwrap(), vec![60, 40]);
+
+		// Fixed shares must cover the whole net amount
+		assert_eq!(compute_split_amounts(101, &shares).unwrap_err(), invalid_split());
+		assert_eq!(compute_split_amounts(99, &shares).unwrap_err(), invalid_split());
+	}
+
+	#[test]
+	fn bps_shares() {
+		let shares = [share(SplitShareAmount::Bps(2500)), share(SplitShareAmount::Bps(7500))];
+		assert_eq!(compute_split_amounts(1000, &shares).unwrap(), vec![250, 750]);
+
+		// The bps shares must total FEE_DENOMINATOR
+		let shares = [share(SplitShareAmount::Bps(2500)), share(SplitShareAmount::Bps(7000))];
+		assert_eq!(compute_split_amounts(1000, &shares).unwrap_err(), invalid_split());
+	}
+
+	#[test]
+	fn mixed_shares() {
+		let shares = [
+			share(SplitShareAmount::Bps(5000)),
+			share(SplitShareAmount::Fixed(100)),
+			share(SplitShareAmount::Bps(5000)),
+		];
+		assert_eq!(compute_split_amounts(1100, &shares).unwrap(), vec![500, 100, 500]);
+
+		// The fixed shares cannot exceed the net amount
+		assert_eq!(compute_split_amounts(99, &shares).unwrap_err(), invalid_split());
+
+		// Bps shares present but not totaling FEE_DENOMINATOR
+		let shares = [share(SplitShareAmount::Fixed(10)), share(SplitShareAmount::Bps(5000))];
+		assert_eq!(compute_split_amounts(100, &shares).unwrap_err(), invalid_split());
+	}
+
+	#[test]
+	fn zero_bps_share() {
+		let shares = [share(SplitShareAmount::Fixed(10)), share(SplitShareAmount::Bps(0))];
+		assert_eq!(compute_split_amounts(100, &shares).unwrap_err(), invalid_split());
+
+		let shares = [share(SplitShareAmount::Bps(10000)), share(SplitShareAmount::Bps(0))];
+		assert_eq!(compute_split_amounts(100, &shares).unwrap_err(), invalid_split());
+	}
+
+	#[test]
+	fn rounding_dust_goes_to_last_bps_share() {
+		let shares = [
+			share(SplitShareAmount::Bps(3333)),
+			share(SplitShareAmount::Fixed(1)),
+			share(SplitShareAmount::Bps(3333)),
+			share(SplitShareAmount::Bps(3334)),
+		];
+		let amounts = compute_split_amounts(101, &shares).unwrap();
+		assert_eq!(amounts, vec![33, 1, 33, 34]);
+		assert_eq!(amounts.iter().sum::<u64>(), 101);
+	}
+}
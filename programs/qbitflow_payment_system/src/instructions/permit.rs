@@ -1,7 +1,6 @@
 
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Token, TokenAccount};
-use anchor_spl::token::Approve;
+use anchor_spl::token_interface::{self, ApproveChecked, Mint, TokenAccount, TokenInterface};
 use crate::errors::*;
 use crate::state::{Authority, Subscription};
 
@@ -35,8 +34,9 @@ impl PermitRegistry {
 		allowance_amount: u64, 
 		subscriber: &Signer<'info>, 
 		authority: &Account<'info, Authority>,
-		token_program: &Program<'info, Token>,
-		subscriber_token_account: &Account<'info, TokenAccount>
+		token_program: &Interface<'info, TokenInterface>,
+		subscriber_token_account: &InterfaceAccount<'info, TokenAccount>,
+		mint: &InterfaceAccount<'info, Mint>,
 	) -> Result<()> {
 		// Now, we need to approve the new allowance with the token program
 		// This new allowance increases the total budget available for subscriptions
@@ -52,18 +52,20 @@ impl PermitRegistry {
 		// Approve the program's delegate PDA to spend the new effective allowance
 		// Note: The actual CPI to approve should be done in the instruction handler where the
 		// user's token account and authority are available.
-		token::approve(
+		token_interface::approve_checked(
 			CpiContext::new(
 				// The token program account should be passed in the instruction context
 				// Here we just use a placeholder; replace with actual account info in the handler
 				token_program.to_account_info(), 
-				Approve {
+				ApproveChecked {
 					to: subscriber_token_account.to_account_info(), // User's token account (placeholder)
+					mint: mint.to_account_info(), // Token mint (required by the checked variant, works for both Token and Token-2022)
 					delegate: authority.to_account_info(), // Program's delegate PDA (placeholder)
 					authority: subscriber.to_account_info(), // User as authority
 				},
 			),
 			new_effective_allowance,
+			mint.decimals,
 		)?;
 
 
@@ -75,7 +77,7 @@ impl PermitRegistry {
 
 	// Check if there is enough allowance to cover a payment of the given amount
 	pub fn has_enough_allowance(&self, amount: u64) -> bool {
-		self.total_used.checked_add(amount).unwrap_or(u64::MAX) <= self.total_allowance
+		self.total_used.saturating_add(amount) <= self.total_allowance
 	}
 
 
@@ -104,6 +106,30 @@ impl PermitRegistry {
 	}
 
 
+	// Add the allowance and used amounts of a subscription moved from another registry (inverse of revoke_allowance)
+	// This does NOT modify the token account's delegate or allowance, it only updates the registry (set_permit must be called to approve the new effective allowance)
+	pub fn restore_allowance(&mut self, subscription: &Subscription) -> Result<()> {
+		self.total_allowance = self.total_allowance.checked_add(subscription.allowance).ok_or(QBitFlowError::Overflow)?;
+		self.total_used = self.total_used.checked_add(subscription.used_allowance).ok_or(QBitFlowError::Overflow)?;
+
+		Ok(())
+	}
+
+
+	// Reduce the allowance of a subscription by `amount`
+	// This does NOT modify the token account's delegate or allowance, it only updates the registry (set_permit must be called to approve the smaller effective allowance)
+	pub fn reduce_allowance(&mut self, amount: u64) -> Result<()> {
+		let total_allowance = self.total_allowance.checked_sub(amount).ok_or(QBitFlowError::Overflow)?;
+
+		if total_allowance < self.total_used {
+			return err!(QBitFlowError::InvalidAmount);
+		}
+		self.total_allowance = total_allowance;
+
+		Ok(())
+	}
+
+
 
 	/**
 	 * This function sets the delegate allowance on the user's token account to the current effective allowance
@@ -112,7 +138,8 @@ impl PermitRegistry {
 	 * Note: This function does NOT modify the permit registry itself, it only sets the delegate
 	 */
 	#[inline(never)]
-	pub fn set_permit<'info>(&mut self, token_program: &Program<'info, Token>, subscriber: &Signer<'info>, authority: &Account<'info, Authority>, subscriber_token_account: &Account<'info, TokenAccount>) -> Result<()> {
+	#[allow(clippy::tabs_in_doc_comments)]
+	pub fn set_permit<'info>(&mut self, token_program: &Interface<'info, TokenInterface>, subscriber: &Signer<'info>, authority: &Account<'info, Authority>, subscriber_token_account: &InterfaceAccount<'info, TokenAccount>, mint: &InterfaceAccount<'info, Mint>) -> Result<()> {
 
 		// Compute the new effective allowance (total_allowance - total_used)
 		let allowance = self.total_allowance.checked_sub(self.total_used).ok_or(QBitFlowError::Overflow)?;
@@ -120,21 +147,125 @@ impl PermitRegistry {
 		// Approve the program's delegate PDA to spend the new effective allowance
 		// Note: The actual CPI to approve should be done in the instruction handler where the
 		// user's token account and authority are available.
-		token::approve(
+		token_interface::approve_checked(
 			CpiContext::new(
 				// The token program account should be passed in the instruction context
 				// Here we just use a placeholder; replace with actual account info in the handler
 				token_program.to_account_info(), 
-				Approve {
+				ApproveChecked {
 					to: subscriber_token_account.to_account_info(), // User's token account (placeholder)
+					mint: mint.to_account_info(), // Token mint (required by the checked variant, works for both Token and Token-2022)
 					delegate: authority.to_account_info(), // Program's delegate PDA (placeholder)
 					authority: subscriber.to_account_info(), // User as authority
 				},
 			),
 			allowance,
+			mint.decimals,
 		)?;
 
 		Ok(())
 	}
 
 }
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::state::{BillingInterval, SubscriptionStatus};
+	use crate::MIN_FREQUENCY;
+
+	fn subscription(allowance: u64, used_allowance: u64) -> Subscription {
+		let mut subscription = crate::state::tests::paused_subscription(BillingInterval::Seconds(MIN_FREQUENCY), 0);
+		subscription.allowance = allowance;
+		subscription.used_allowance = used_allowance;
+		subscription
+	}
+
+	#[test]
+	fn merchant_cancel_revokes_the_subscription_share() {
+		let canceled = subscription(1000, 300);
+		let other = subscription(500, 100);
+		let mut permit_registry = PermitRegistry { total_allowance: 1500, total_used: 400, bump: 255 };
+
+		// Subscriptions can be canceled by their merchant from any status
+		for status in [SubscriptionStatus::Trialing, SubscriptionStatus::Active, SubscriptionStatus::PastDue, SubscriptionStatus::Paused, SubscriptionStatus::PendingCancel] {
+			let mut subscription = canceled.clone();
+			subscription.status = status;
+			subscription.set_status([0; 16], SubscriptionStatus::Ended).unwrap();
+		}
+
+		// The other subscriptions keep their allowance, and the registry stays open
+		permit_registry.revoke_allowance(&canceled).unwrap();
+		assert_eq!((permit_registry.total_allowance, permit_registry.total_used), (other.allowance, other.used_allowance));
+		assert!(permit_registry.has_enough_allowance(400));
+		assert!(!permit_registry.has_enough_allowance(401));
+
+		// A subscription cannot be revoked twice
+		permit_registry.revoke_allowance(&canceled).unwrap_err();
+	}
+
+	#[test]
+	fn revoke_every_subscription() {
+		let subscriptions = [subscription(1000, 300), subscription(500, 0), subscription(250, 250)];
+		let mut permit_registry = PermitRegistry { total_allowance: 0, total_used: 0, bump: 255 };
+		for subscription in &subscriptions {
+			permit_registry.restore_allowance(subscription).unwrap();
+		}
+		assert_eq!((permit_registry.total_allowance, permit_registry.total_used), (1750, 550));
+
+		// Any subscription left out keeps an allowance in the registry
+		for subscription in &subscriptions[..2] {
+			permit_registry.revoke_allowance(subscription).unwrap();
+		}
+		assert_ne!(permit_registry.total_allowance, 0);
+
+		// Once every subscription is revoked, the registry is empty and can be closed
+		permit_registry.revoke_allowance(&subscriptions[2]).unwrap();
+		assert_eq!((permit_registry.total_allowance, permit_registry.total_used), (0, 0));
+	}
+
+	#[test]
+	fn move_a_subscription_to_another_registry() {
+		let transferred = subscription(1000, 300);
+		let mut permit_registry = PermitRegistry { total_allowance: 1500, total_used: 400, bump: 255 };
+		let mut new_permit_registry = PermitRegistry { total_allowance: 200, total_used: 50, bump: 254 };
+
+		permit_registry.revoke_allowance(&transferred).unwrap();
+		new_permit_registry.restore_allowance(&transferred).unwrap();
+
+		// The allowance share (used part included) moves as a whole, so both effective allowances stay right
+		assert_eq!((permit_registry.total_allowance, permit_registry.total_used), (500, 100));
+		assert_eq!((new_permit_registry.total_allowance, new_permit_registry.total_used), (1200, 350));
+		assert_eq!(permit_registry.total_allowance + new_permit_registry.total_allowance, 1700);
+		assert_eq!(permit_registry.total_used + new_permit_registry.total_used, 450);
+		assert!(new_permit_registry.has_enough_allowance(850));
+		assert!(!new_permit_registry.has_enough_allowance(851));
+
+		// Moving it back restores the original totals
+		new_permit_registry.revoke_allowance(&transferred).unwrap();
+		permit_registry.restore_allowance(&transferred).unwrap();
+		assert_eq!((permit_registry.total_allowance, permit_registry.total_used), (1500, 400));
+		assert_eq!((new_permit_registry.total_allowance, new_permit_registry.total_used), (200, 50));
+
+		let mut full_permit_registry = PermitRegistry { total_allowance: u64::MAX, total_used: 0, bump: 253 };
+		assert_eq!(full_permit_registry.restore_allowance(&transferred).unwrap_err(), QBitFlowError::Overflow.into());
+	}
+
+	#[test]
+	fn reduce_allowance_down_to_the_used_amount() {
+		let mut permit_registry = PermitRegistry { total_allowance: 1500, total_used: 400, bump: 255 };
+
+		permit_registry.reduce_allowance(600).unwrap();
+		assert_eq!((permit_registry.total_allowance, permit_registry.total_used), (900, 400));
+
+		// Never below what has already been used
+		assert_eq!(permit_registry.reduce_allowance(501).unwrap_err(), QBitFlowError::InvalidAmount.into());
+		assert_eq!(permit_registry.reduce_allowance(u64::MAX).unwrap_err(), QBitFlowError::Overflow.into());
+		assert_eq!(permit_registry.total_allowance, 900);
+
+		permit_registry.reduce_allowance(500).unwrap();
+		assert_eq!((permit_registry.total_allowance, permit_registry.total_used), (400, 400));
+		assert!(!permit_registry.has_enough_allowance(1));
+	}
+}
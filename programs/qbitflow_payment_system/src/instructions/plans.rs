@@ -0,0 +1,113 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::Mint;
+use crate::state::*;
+use crate::errors::*;
+use crate::PLAN_PDA_SEED;
+
+#[derive(Accounts)]
+#[instruction(plan_id: [u8; 16])]
+pub struct CreatePlan<'info> {
+	#[account()]
+	pub authority_and_owner: AuthorityAndOwner<'info>,
+
+	// The merchant defining the plan, needs to sign the transaction
+	pub merchant: Signer<'info>,
+
+	// Unique plan account derived from the merchant and the plan id
+	#[account(
+		init,
+		payer = authority_and_owner.owner, // authority owner pays for the plan account creation
+		space = Plan::LEN,
+		seeds = [PLAN_PDA_SEED, merchant.key().as_ref(), plan_id.as_ref()],
+		bump
+	)]
+	pub plan: Account<'info, Plan>,
+
+	/// CHECK: The organization receiving a portion of the fees of the subscriptions to this plan
+	pub organization: UncheckedAccount<'info>,
+
+	// The token mint the plan is billed in (Token or Token-2022)
+	pub mint: InterfaceAccount<'info, Mint>,
+
+	pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(plan_id: [u8; 16])]
+pub struct SetPlanActive<'info> {
+	#[account()]
+	pub authority_and_owner: AuthorityAndOwner<'info>,
+
+	// Only the merchant of the plan can update it
+	pub merchant: Signer<'info>,
+
+	#[account(
+		mut,
+		seeds = [PLAN_PDA_SEED, merchant.key().as_ref(), plan_id.as_ref()],
+		bump = plan.bump,
+		has_one = merchant @ QBitFlowError::Unauthorized,
+	)]
+	pub plan: Account<'info, Plan>,
+}
+
+
+/**
+ * Create a subscription plan
+ * This must be signed by the merchant, the plan is derived from the merchant and the plan id
+ * Emits a PlanCreated event
+ */
+pub fn create_plan(
+	ctx: Context<CreatePlan>,
+	_plan_id: [u8; 16],
+	price: u64,
+	billing_interval: BillingInterval,
+	trial_period: u32,
+) -> Result<()> {
+	if price == 0 {
+		return err!(QBitFlowError::ZeroAmount);
+	}
+
+	billing_interval.validate()?;
+
+	ctx.accounts.plan.set_inner(Plan {
+		merchant: ctx.accounts.merchant.key(),
+		organization: ctx.accounts.organization.key(),
+		mint: ctx.accounts.mint.key(),
+		price,
+		billing_interval,
+		trial_period,
+		active: true,
+		bump: ctx.bumps.plan,
+	});
+
+	emit!(PlanCreated {
+		plan: ctx.accounts.plan.key(),
+		merchant: ctx.accounts.merchant.key(),
+		price,
+		billing_interval,
+		trial_period,
+	});
+
+	Ok(())
+}
+
+
+/**
+ * Activate or deactivate a plan
+ * Inactive plans do not accept new subscriptions, existing subscriptions are still billed
+ * Emits a PlanActiveUpdated event
+ */
+pub fn set_plan_active(
+	ctx: Context<SetPlanActive>,
+	_plan_id: [u8; 16],
+	active: bool,
+) -> Result<()> {
+	ctx.accounts.plan.active = active;
+
+	emit!(PlanActiveUpdated {
+		plan: ctx.accounts.plan.key(),
+		active,
+	});
+
+	Ok(())
+}
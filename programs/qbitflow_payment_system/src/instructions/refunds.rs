@@ -0,0 +1,128 @@
+use anchor_lang::prelude::*;
+use anchor_lang::system_program;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface, TransferChecked};
+use crate::instructions::transfer_hook::transfer_checked_with_hook;
+use crate::state::*;
+use crate::errors::*;
+use crate::PAYMENT_RECEIPT_PDA_SEED;
+
+#[derive(Accounts)]
+#[instruction(uuid: [u8; 16])]
+pub struct RefundPayment<'info> {
+	#[account()]
+	pub authority_and_owner: AuthorityAndOwner<'info>,
+
+	// Receipt of the payment being refunded, derived from the uuid of the payment
+	// Payments made without a receipt cannot be refunded through the program
+	#[account(
+		mut,
+		seeds = [PAYMENT_RECEIPT_PDA_SEED, uuid.as_ref()],
+		bump = payment_receipt.bump,
+		has_one = merchant @ QBitFlowError::Unauthorized, // Only the merchant of the payment can refund it
+		has_one = payer @ QBitFlowError::Unauthorized, // Refunds always go back to the original payer
+	)]
+	pub payment_receipt: Account<'info, PaymentReceipt>,
+
+	// The merchant refunding the payment, needs to sign the transaction
+	#[account(mut)]
+	pub merchant: Signer<'info>,
+
+	/// CHECK: The original payer of the payment (verified against the receipt)
+	#[account(mut)]
+	pub payer: UncheckedAccount<'info>,
+
+	// The following accounts are only required for token payments
+
+	// The merchant's token account from which the refund is made
+	#[account(mut)]
+	pub merchant_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+	// The payer's token account receiving the refund
+	#[account(mut)]
+	pub payer_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+	// The mint of the payment (Token or Token-2022), must match the receipt
+	pub mint: Option<InterfaceAccount<'info, Mint>>,
+
+	pub token_program: Option<Interface<'info, TokenInterface>>,
+
+	pub system_program: Program<'info, System>,
+}
+
+
+/**
+ * Refund a one-time payment (fully or partially)
+ * This must be signed by the merchant of the payment, and requires a payment receipt
+ * The refund is sent back to the original payer (token account for token payments, lamports for SOL payments)
+ * The cumulative refunded amount is tracked in the receipt, and can never exceed the amount paid
+ * Emits a PaymentRefunded event
+ */
+pub fn refund_payment<'info>(
+	ctx: Context<'_, '_, '_, 'info, RefundPayment<'info>>,
+	uuid: [u8; 16],
+	amount: u64,
+) -> Result<()> {
+	if amount == 0 {
+		return err!(QBitFlowError::ZeroAmount);
+	}
+
+	let payment_receipt = &mut ctx.accounts.payment_receipt;
+
+	// The total refunded can never exceed the amount of the payment
+	let total_refunded = payment_receipt.record_refund(amount)?;
+
+	match payment_receipt.mint {
+		None => {
+			// SOL payment, refund in lamports
+			system_program::transfer(
+				CpiContext::new(
+					ctx.accounts.system_program.to_account_info(),
+					system_program::Transfer {
+						from: ctx.accounts.merchant.to_account_info(),
+						to: ctx.accounts.payer.to_account_info(),
+					},
+				),
+				amount,
+			)?;
+		}
+		Some(receipt_mint) => {
+			// Token payment, refund in tokens (from the merchant's token account to the payer's token account)
+			let (Some(merchant_token_account), Some(payer_token_account), Some(mint), Some(token_program)) = (
+				&ctx.accounts.merchant_token_account,
+				&ctx.accounts.payer_token_account,
+				&ctx.accounts.mint,
+				&ctx.accounts.token_program,
+			) else {
+				return err!(QBitFlowError::InvalidTokenAccounts);
+			};
+
+			if mint.key() != receipt_mint
+				|| payer_token_account.mint != receipt_mint
+				|| payer_token_account.owner != payment_receipt.payer {
+				return err!(QBitFlowError::InvalidTokenAccounts);
+			}
+
+			transfer_checked_with_hook(
+				CpiContext::new(
+					token_program.to_account_info(),
+					TransferChecked {
+						from: merchant_token_account.to_account_info(),
+						mint: mint.to_account_info(),
+						to: payer_token_account.to_account_info(),
+						authority: ctx.accounts.merchant.to_account_info(),
+					},
+				).with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+				amount,
+				mint.decimals,
+			)?;
+		}
+	}
+
+	emit!(PaymentRefunded {
+		uuid,
+		amount,
+		total_refunded,
+	});
+
+	Ok(())
+}
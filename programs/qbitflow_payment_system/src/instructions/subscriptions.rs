@@ -1,13 +1,15 @@
-use anchor_lang::prelude::*;
+use anchor_lang::{prelude::*, solana_program::program_option::COption};
 use anchor_spl::associated_token::AssociatedToken;
-use anchor_spl::token::{self, Token, TokenAccount, Transfer};
+use anchor_spl::token_interface::{self, Mint, Revoke, TokenAccount, TokenInterface, TransferChecked};
 use crate::instructions::compute_refund::{compute_refund, ComputeRefundData};
-use crate::{state::*, MIN_FREQUENCY, PERMIT_REGISTRY_PDA_SEED, SUBSCRIPTION_PDA_SEED};
+use crate::instructions::transfer_fee::{TransferAmounts, TransferFeePolicy};
+use crate::instructions::transfer_hook::transfer_checked_with_hook;
+use crate::{state::*, PERMIT_REGISTRY_PDA_SEED, SUBSCRIPTION_PDA_SEED};
 use crate::errors::*;
 use crate::permit::{PermitRegistry};
 
 #[derive(Accounts)]
-#[instruction(uuid: [u8; 16], frequency: u32, allowance: u64)]
+#[instruction(uuid: [u8; 16])]
 pub struct CreateSubscription<'info> {
     #[account()]
 	pub authority_and_owner: AuthorityAndOwner<'info>,
@@ -46,9 +48,10 @@ pub struct CreateSubscription<'info> {
     #[account(
 		mut,
 		associated_token::mint = mint,
-		associated_token::authority = subscriber
+		associated_token::authority = subscriber,
+		associated_token::token_program = token_program
 	)]
-    pub subscriber_token_account: Account<'info, TokenAccount>,
+    pub subscriber_token_account: InterfaceAccount<'info, TokenAccount>,
 
 
 	/// CHECK: The merchant receiving the payments for this subscription (main account). Must be initialized
@@ -60,9 +63,10 @@ pub struct CreateSubscription<'info> {
 		init_if_needed,
 		payer = authority_and_owner.owner, // authority pays for token account initialization (merchant)
 		associated_token::mint = mint,
-		associated_token::authority = merchant
+		associated_token::authority = merchant,
+		associated_token::token_program = token_program
 	)]
-    pub merchant_token_account: Account<'info, TokenAccount>,
+    pub merchant_token_account: InterfaceAccount<'info, TokenAccount>,
 
 	// Ensures this token account belongs to authority.owner
 	// No need to pass the address from the client side (and even though we do, it will be ignored)
@@ -70,14 +74,15 @@ pub struct CreateSubscription<'info> {
         init_if_needed,
 		payer = authority_and_owner.owner,
 		associated_token::mint = mint,
-		associated_token::authority = authority_and_owner.owner // fee recipient is authority owner
+		associated_token::authority = authority_and_owner.owner, // fee recipient is authority owner
+		associated_token::token_program = token_program
     )]
-    pub fee_recipient_token_account: Account<'info, TokenAccount>,
+    pub fee_recipient_token_account: InterfaceAccount<'info, TokenAccount>,
 
 
     
 	// The token mint used for this subscription
-    pub mint: Account<'info, anchor_spl::token::Mint>,
+    pub mint: InterfaceAccount<'info, Mint>,
     
 
 	/// CHECK: This is the organization receiving a portion of the fees (optional). Must be initialized
@@ -88,18 +93,23 @@ pub struct CreateSubscription<'info> {
 		init_if_needed,
 		payer = authority_and_owner.owner, // organization pays for their token own account initialization
 		associated_token::mint = mint,
-		associated_token::authority = organization
+		associated_token::authority = organization,
+		associated_token::token_program = token_program
 	)]
-    pub organization_token_account: Account<'info, TokenAccount>,
+    pub organization_token_account: InterfaceAccount<'info, TokenAccount>,
+
+	// Plan to subscribe to (optional, standalone subscription if not provided)
+	// The merchant, organization, mint, price and billing interval of the subscription must match the plan
+	pub plan: Option<Box<Account<'info, Plan>>>,
 
     
     pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
+    pub token_program: Interface<'info, TokenInterface>,
 	pub associated_token_program: Program<'info, AssociatedToken>,
 }
 
 #[derive(Accounts)]
-#[instruction(amount: u64, fee_bps: u16, uuid: [u8; 16], frequency: u32, organization_fee_bps: u16)]
+#[instruction(amount: u64, fee_bps: u16, uuid: [u8; 16], organization_fee_bps: u16)]
 pub struct ExecuteSubscription<'info> {
     #[account()]
 	pub authority_and_owner: AuthorityAndOwner<'info>,
@@ -131,16 +141,19 @@ pub struct ExecuteSubscription<'info> {
     #[account(
 		mut,
 		associated_token::mint = mint,
-		associated_token::authority = subscription.subscriber
+		associated_token::authority = subscription.subscriber,
+		associated_token::token_program = token_program
 	)]
-    pub subscriber_token_account: Account<'info, TokenAccount>,
+    pub subscriber_token_account: InterfaceAccount<'info, TokenAccount>,
 
     
-	// Merchant's token account (will be verified comparing hash)
-    #[account(mut)]
-    pub merchant_token_account: Account<'info, TokenAccount>,
+	// Merchant's token account, must match the one stored in the subscription
+    #[account(mut, address = subscription.merchant_token_account @ QBitFlowError::InvalidSubscriptionParameters)]
+    pub merchant_token_account: InterfaceAccount<'info, TokenAccount>,
 
-	pub mint: Account<'info, anchor_spl::token::Mint>,
+	// Token mint of the subscription, must match the one stored in the subscription
+	#[account(address = subscription.mint @ QBitFlowError::InvalidSubscriptionParameters)]
+	pub mint: InterfaceAccount<'info, Mint>,
     
 
 	// Fee recipient token account (ATA of the authority.owner)
@@ -149,19 +162,79 @@ pub struct ExecuteSubscription<'info> {
     #[account(
 		mut,
 		associated_token::mint = mint,
-		associated_token::authority = authority_and_owner.owner // fee recipient is authority owner
+		associated_token::authority = authority_and_owner.owner, // fee recipient is authority owner
+		associated_token::token_program = token_program
 	)]
-    pub fee_recipient_token_account: Account<'info, TokenAccount>,
+    pub fee_recipient_token_account: InterfaceAccount<'info, TokenAccount>,
     
 
-	// Organization token account (ATA of the organization_account)
-	// Will be verified during hash comparison
-    #[account(mut)]
-    pub organization_token_account: Account<'info, TokenAccount>,
+	// Organization token account, must match the one stored in the subscription
+    #[account(mut, address = subscription.organization_token_account @ QBitFlowError::InvalidSubscriptionParameters)]
+    pub organization_token_account: InterfaceAccount<'info, TokenAccount>,
+
+	// Plan of the subscription, required if the subscription was created from a plan (the price is read from it)
+	#[account(address = subscription.plan @ QBitFlowError::InvalidSubscriptionParameters)]
+	pub plan: Option<Box<Account<'info, Plan>>>,
     
 	pub system_program: Program<'info, System>,
 	pub associated_token_program: Program<'info, AssociatedToken>,
-    pub token_program: Program<'info, Token>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(uuid: [u8; 16])]
+pub struct SettlePaygSubscription<'info> {
+    #[account()]
+	pub authority_and_owner: AuthorityAndOwner<'info>,
+
+    #[account(
+        mut,
+        seeds = [SUBSCRIPTION_PDA_SEED, uuid.as_ref()],
+        bump = subscription.bump
+    )]
+    pub subscription: Account<'info, Subscription>,
+
+	#[account(
+		mut,
+		seeds = [PERMIT_REGISTRY_PDA_SEED, subscription.subscriber.key().as_ref(), mint.key().as_ref()],
+		bump = permit_registry.bump
+	)]
+	pub permit_registry: Account<'info, PermitRegistry>,
+
+	// Only the merchant of the subscription can submit the final usage charge
+	#[account(address = subscription.merchant @ QBitFlowError::Unauthorized)]
+	pub merchant: Signer<'info>,
+
+	// The subscriber's token account from which the final payment is made
+    #[account(
+		mut,
+		associated_token::mint = mint,
+		associated_token::authority = subscription.subscriber,
+		associated_token::token_program = token_program
+	)]
+    pub subscriber_token_account: InterfaceAccount<'info, TokenAccount>,
+
+	// Merchant's token account, must match the one stored in the subscription
+    #[account(mut, address = subscription.merchant_token_account @ QBitFlowError::InvalidSubscriptionParameters)]
+    pub merchant_token_account: InterfaceAccount<'info, TokenAccount>,
+
+	#[account(address = subscription.mint @ QBitFlowError::InvalidSubscriptionParameters)]
+	pub mint: InterfaceAccount<'info, Mint>,
+
+	// Fee recipient token account (ATA of the authority.owner)
+    #[account(
+		mut,
+		associated_token::mint = mint,
+		associated_token::authority = authority_and_owner.owner, // fee recipient is authority owner
+		associated_token::token_program = token_program
+	)]
+    pub fee_recipient_token_account: InterfaceAccount<'info, TokenAccount>,
+
+	// Organization token account, must match the one stored in the subscription
+    #[account(mut, address = subscription.organization_token_account @ QBitFlowError::InvalidSubscriptionParameters)]
+    pub organization_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
 }
 
 #[derive(Accounts)]
@@ -191,7 +264,68 @@ pub struct CancelSubscription<'info> {
     pub subscriber: Signer<'info>,
 
 
-	pub mint: Account<'info, anchor_spl::token::Mint>,
+	#[account(address = subscription.mint @ QBitFlowError::InvalidSubscriptionParameters)]
+	pub mint: InterfaceAccount<'info, Mint>,
+}
+
+
+#[derive(Accounts)]
+#[instruction(uuid: [u8; 16])]
+pub struct CloseCanceledSubscription<'info> {
+	#[account()]
+	pub authority_and_owner: AuthorityAndOwner<'info>,
+
+    #[account(
+        mut,
+        seeds = [SUBSCRIPTION_PDA_SEED, uuid.as_ref()],
+        bump = subscription.bump
+    )]
+    pub subscription: Account<'info, Subscription>,
+
+	// The allowance of the subscription was already released, so the permit registry is optional (it may have been closed since)
+	// If provided and empty, it is closed as well
+	#[account(
+		mut,
+		seeds = [PERMIT_REGISTRY_PDA_SEED, subscription.subscriber.as_ref(), subscription.mint.as_ref()],
+		bump = permit_registry.bump
+	)]
+	pub permit_registry: Option<Account<'info, PermitRegistry>>,
+}
+
+
+#[derive(Accounts)]
+#[instruction(uuid: [u8; 16])]
+pub struct PauseSubscription<'info> {
+	#[account()]
+	pub authority_and_owner: AuthorityAndOwner<'info>,
+
+    #[account(
+        mut,
+        seeds = [SUBSCRIPTION_PDA_SEED, uuid.as_ref()],
+        bump = subscription.bump
+    )]
+    pub subscription: Account<'info, Subscription>,
+
+	// The subscriber or the merchant of the subscription (verified in the handler)
+	pub signer: Signer<'info>,
+}
+
+
+#[derive(Accounts)]
+#[instruction(uuid: [u8; 16])]
+pub struct ResumeSubscription<'info> {
+	#[account()]
+	pub authority_and_owner: AuthorityAndOwner<'info>,
+
+    #[account(
+        mut,
+        seeds = [SUBSCRIPTION_PDA_SEED, uuid.as_ref()],
+        bump = subscription.bump
+    )]
+    pub subscription: Account<'info, Subscription>,
+
+	// The subscriber or the merchant of the subscription (verified in the handler)
+	pub signer: Signer<'info>,
 }
 
 
@@ -225,11 +359,13 @@ pub struct UpdateMaxAmount<'info> {
     #[account(
 		mut,
 		associated_token::mint = mint,
-		associated_token::authority = subscription.subscriber
+		associated_token::authority = subscription.subscriber,
+		associated_token::token_program = token_program
 	)]
-    pub subscriber_token_account: Account<'info, TokenAccount>,
+    pub subscriber_token_account: InterfaceAccount<'info, TokenAccount>,
 
-	pub mint: Account<'info, anchor_spl::token::Mint>,
+	#[account(address = subscription.mint @ QBitFlowError::InvalidSubscriptionParameters)]
+	pub mint: InterfaceAccount<'info, Mint>,
 
 	// Fee recipient token account (ATA of the authority.owner)
 	// Verifies that the token account belongs to authority.owner
@@ -237,11 +373,12 @@ pub struct UpdateMaxAmount<'info> {
     #[account(
 		mut,
 		associated_token::mint = mint,
-		associated_token::authority = authority_and_owner.owner // fee recipient is authority owner
+		associated_token::authority = authority_and_owner.owner, // fee recipient is authority owner
+		associated_token::token_program = token_program
 	)]
-    pub fee_recipient_token_account: Account<'info, TokenAccount>,
+    pub fee_recipient_token_account: InterfaceAccount<'info, TokenAccount>,
 
-	pub token_program: Program<'info, Token>,
+	pub token_program: Interface<'info, TokenInterface>,
 }
 
 
@@ -265,7 +402,102 @@ pub struct ForceCancelSubscription<'info> {
 	)]
 	pub permit_registry: Account<'info, PermitRegistry>,
 
-	pub mint: Account<'info, anchor_spl::token::Mint>,
+	#[account(address = subscription.mint @ QBitFlowError::InvalidSubscriptionParameters)]
+	pub mint: InterfaceAccount<'info, Mint>,
+}
+
+
+#[derive(Accounts)]
+#[instruction(uuid: [u8; 16])]
+pub struct MerchantCancelSubscription<'info> {
+	#[account()]
+	pub authority_and_owner: AuthorityAndOwner<'info>,
+
+    #[account(
+        mut,
+        seeds = [SUBSCRIPTION_PDA_SEED, uuid.as_ref()],
+        bump = subscription.bump
+    )]
+    pub subscription: Account<'info, Subscription>,
+
+	#[account(
+		mut,
+		seeds = [PERMIT_REGISTRY_PDA_SEED, subscription.subscriber.key().as_ref(), mint.key().as_ref()],
+		bump = permit_registry.bump
+	)]
+	pub permit_registry: Account<'info, PermitRegistry>,
+
+	// Only the merchant recorded in the subscription terms can cancel it
+	#[account(address = subscription.merchant @ QBitFlowError::Unauthorized)]
+	pub merchant: Signer<'info>,
+
+	#[account(address = subscription.mint @ QBitFlowError::InvalidSubscriptionParameters)]
+	pub mint: InterfaceAccount<'info, Mint>,
+}
+
+
+#[derive(Accounts)]
+#[instruction(uuid: [u8; 16])]
+pub struct ChangeSubscriptionPlan<'info> {
+	#[account()]
+	pub authority_and_owner: AuthorityAndOwner<'info>,
+
+    #[account(
+        mut,
+        seeds = [SUBSCRIPTION_PDA_SEED, uuid.as_ref()],
+        bump = subscription.bump
+    )]
+    pub subscription: Box<Account<'info, Subscription>>,
+
+	#[account(
+		mut,
+		seeds = [PERMIT_REGISTRY_PDA_SEED, subscription.subscriber.key().as_ref(), mint.key().as_ref()],
+		bump = permit_registry.bump
+	)]
+	pub permit_registry: Box<Account<'info, PermitRegistry>>,
+
+	// Only the subscriber can change the plan of their subscription
+	#[account(mut, address = subscription.subscriber @ QBitFlowError::Unauthorized)]
+    pub subscriber: Signer<'info>,
+
+	// The subscriber's token account from which payments are made
+    #[account(
+		mut,
+		associated_token::mint = mint,
+		associated_token::authority = subscriber,
+		associated_token::token_program = token_program
+	)]
+    pub subscriber_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+	// Merchant's token account, must match the one stored in the subscription
+    #[account(mut, address = subscription.merchant_token_account @ QBitFlowError::InvalidSubscriptionParameters)]
+    pub merchant_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+	// Fee recipient token account (ATA of the authority.owner)
+    #[account(
+		mut,
+		associated_token::mint = mint,
+		associated_token::authority = authority_and_owner.owner, // fee recipient is authority owner
+		associated_token::token_program = token_program
+	)]
+    pub fee_recipient_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+	// Organization token account, must match the one stored in the subscription
+    #[account(mut, address = subscription.organization_token_account @ QBitFlowError::InvalidSubscriptionParameters)]
+    pub organization_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+	// The plan to switch to, must be a plan of the same merchant, in the same mint, with the same organization
+	#[account(
+		constraint = new_plan.merchant == subscription.merchant @ QBitFlowError::InvalidSubscriptionParameters,
+		constraint = new_plan.mint == subscription.mint @ QBitFlowError::InvalidSubscriptionParameters,
+		constraint = new_plan.organization == organization_token_account.owner @ QBitFlowError::InvalidSubscriptionParameters,
+	)]
+	pub new_plan: Box<Account<'info, Plan>>,
+
+	#[account(address = subscription.mint @ QBitFlowError::InvalidSubscriptionParameters)]
+	pub mint: Box<InterfaceAccount<'info, Mint>>,
+
+	pub token_program: Interface<'info, TokenInterface>,
 }
 
 
@@ -281,9 +513,10 @@ pub struct IncreaseAllowance<'info> {
     #[account(
 		mut,
 		associated_token::mint = mint,
-		associated_token::authority = authority_and_owner.owner // fee recipient is authority owner
+		associated_token::authority = authority_and_owner.owner, // fee recipient is authority owner
+		associated_token::token_program = token_program
 	)]
-    pub fee_recipient_token_account: Account<'info, TokenAccount>,
+    pub fee_recipient_token_account: InterfaceAccount<'info, TokenAccount>,
 
     #[account(
         mut,
@@ -313,108 +546,414 @@ pub struct IncreaseAllowance<'info> {
     #[account(
 		mut,
 		associated_token::mint = mint,
-		associated_token::authority = subscriber
+		associated_token::authority = subscriber,
+		associated_token::token_program = token_program
 	)]
-    pub subscriber_token_account: Account<'info, TokenAccount>,
+    pub subscriber_token_account: InterfaceAccount<'info, TokenAccount>,
 
 	// The token mint used for this subscription
-    pub mint: Account<'info, anchor_spl::token::Mint>,
+	#[account(address = subscription.mint @ QBitFlowError::InvalidSubscriptionParameters)]
+    pub mint: InterfaceAccount<'info, Mint>,
 
-	pub token_program: Program<'info, Token>,
+	pub token_program: Interface<'info, TokenInterface>,
 }
 
 
-/**
- * Create a regular subscription
- * Emits a SubscriptionCreated event
- */
-pub fn create_subscription(
-    ctx: Context<CreateSubscription>,
-    uuid: [u8; 16],
-	amount: u64,
-	max_amount: u64,
-    frequency: u32,
-    allowance: u64,
-	compute_refund_params: ComputeRefundData,
-	is_payg: bool,
-) -> Result<()> {
-	// let (next_payment_due, remaining_allowance) = _create_subscription(ctx, uuid, amount, max_amount, frequency, allowance, false, compute_refund_params)?;
-	if frequency < MIN_FREQUENCY {
-        return err!(QBitFlowError::InvalidFrequency);
-    }
+#[derive(Accounts)]
+#[instruction(uuid: [u8; 16])]
+pub struct TransferSubscription<'info> {
+	#[account()]
+	pub authority_and_owner: AuthorityAndOwner<'info>,
 
-	if max_amount <= amount {
-		return err!(QBitFlowError::InvalidAmount);
-	}
+    #[account(
+        mut,
+        seeds = [SUBSCRIPTION_PDA_SEED, uuid.as_ref()],
+        bump = subscription.bump
+    )]
+    pub subscription: Box<Account<'info, Subscription>>,
 
-	// Add allowance to the permit registry
-	let permit_registry = &mut ctx.accounts.permit_registry;
+	// The current subscriber, must sign the transaction
+    #[account(
+		mut,
+		address = subscription.subscriber @ QBitFlowError::Unauthorized
+	)]
+    pub subscriber: Signer<'info>,
 
-	if permit_registry.bump == 0 {
-		// Newly initialized, set initial values
-		permit_registry.bump = ctx.bumps.permit_registry;
-	}
+	// The wallet receiving the subscription, must sign the transaction as well
+	#[account(mut)]
+	pub new_subscriber: Signer<'info>,
 
-	// Add allowance entry in permit registry
-	permit_registry.add_allowance(allowance, &ctx.accounts.subscriber, &ctx.accounts.authority_and_owner.authority, &ctx.accounts.token_program, &ctx.accounts.subscriber_token_account)?;
+	// Permit registry of the current subscriber, the allowance share of the subscription is removed from it
+	#[account(
+		mut,
+		seeds = [PERMIT_REGISTRY_PDA_SEED, subscriber.key().as_ref(), mint.key().as_ref()],
+		bump = permit_registry.bump
+	)]
+	pub permit_registry: Box<Account<'info, PermitRegistry>>,
 
+	// Permit registry of the new subscriber, the allowance share of the subscription is moved to it
+	#[account(
+		init_if_needed, // First time initialization if needed
+		payer = authority_and_owner.owner, // authority owner pays for the permit registry account creation (and is reimbursed if it's closed later)
+		space = PermitRegistry::LEN,
+		seeds = [PERMIT_REGISTRY_PDA_SEED, new_subscriber.key().as_ref(), mint.key().as_ref()],
+		bump
+	)]
+	pub new_permit_registry: Box<Account<'info, PermitRegistry>>,
 
-	// Now initialize the subscription account
-    let subscription = &mut ctx.accounts.subscription;
+	// The current subscriber's token account, approved again with its smaller effective allowance
+    #[account(
+		mut,
+		associated_token::mint = mint,
+		associated_token::authority = subscriber,
+		associated_token::token_program = token_program
+	)]
+    pub subscriber_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
 
+	// The new subscriber's token account, from which the next payments will be made
+	// This one needs to be initiated by the new subscriber beforehand
+    #[account(
+		mut,
+		associated_token::mint = mint,
+		associated_token::authority = new_subscriber,
+		associated_token::token_program = token_program
+	)]
+    pub new_subscriber_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
 
-    let next_payment_due: i64;
-	if is_payg {
-		// Next payment due is now + frequency (no trial period for pay-as-you-go)
-		// And the billing is done at the end of the period
-		next_payment_due = Clock::get()?.unix_timestamp + (frequency as i64);
-	} else {
-		// For regular subscriptions, the first payment is due immediately
-		next_payment_due = Clock::get()?.unix_timestamp;
-	}
+	#[account(address = subscription.mint @ QBitFlowError::InvalidSubscriptionParameters)]
+    pub mint: Box<InterfaceAccount<'info, Mint>>,
 
-    
-    subscription.subscriber = ctx.accounts.subscriber.key();
-    subscription.next_payment_due = next_payment_due;
-	subscription.allowance = allowance;
-	subscription.used_allowance = 0;
-	subscription.stopped = false;
-	subscription.max_amount = max_amount;
-    subscription.bump = ctx.bumps.subscription;
-	subscription.last_payment_amount = amount;
+	pub system_program: Program<'info, System>,
+	pub token_program: Interface<'info, TokenInterface>,
+}
 
 
-	// Create the hash of the subscription for uniqueness, and to ensure the parameters match during execution
-	// We do this, and pass the required parameters to the execute function, instead of storing them directly, to save space (and cost)
-	subscription.subscription_hash = create_subscription_hash(
-		&ctx.accounts.merchant_token_account.key(),
-		&ctx.accounts.subscriber_token_account.key(), 
-		frequency, 
-		&ctx.accounts.organization_token_account.key()
-	);
+#[derive(Accounts)]
+pub struct RevokeAll<'info> {
+	#[account()]
+	pub authority_and_owner: AuthorityAndOwner<'info>,
 
-	// Compute refund in tokens for the authority owner to refund the compute cost paid in SOL
-	let refund_result = compute_refund(uuid, max_amount - amount, compute_refund_params, CpiContext::new(
-		ctx.accounts.token_program.to_account_info(),
-		Transfer {
-			from: ctx.accounts.subscriber_token_account.to_account_info(),
-			to: ctx.accounts.fee_recipient_token_account.to_account_info(),
-			authority: ctx.accounts.subscriber.to_account_info(),
-		},
-	))?;
+	// Permit registry of the subscriber for this mint, closed once every subscription is canceled
+	#[account(
+		mut,
+		seeds = [PERMIT_REGISTRY_PDA_SEED, subscriber.key().as_ref(), mint.key().as_ref()],
+		bump = permit_registry.bump
+	)]
+	pub permit_registry: Account<'info, PermitRegistry>,
 
+	#[account(mut)]
+    pub subscriber: Signer<'info>,
 
-	// Update the used allowance with the refunded amount (best-effort, if it fails, the authority owner pays the compute cost in SOL)
-	permit_registry.use_allowance(refund_result)?; // And update the permit registry as well (since the total allowance used has increased)
-	subscription.used_allowance = refund_result; // Update the used allowance for the subscription
+	// The subscriber's token account, whose delegate is revoked
+    #[account(
+		mut,
+		associated_token::mint = mint,
+		associated_token::authority = subscriber,
+		associated_token::token_program = token_program
+	)]
+    pub subscriber_token_account: InterfaceAccount<'info, TokenAccount>,
 
+    pub mint: InterfaceAccount<'info, Mint>,
 
-    emit!(SubscriptionCreated {
+	pub token_program: Interface<'info, TokenInterface>,
+}
+
+
+#[derive(Accounts)]
+#[instruction(uuid: [u8; 16])]
+pub struct DecreaseAllowance<'info> {
+	#[account()]
+	pub authority_and_owner: AuthorityAndOwner<'info>,
+
+    #[account(
+        mut,
+        seeds = [SUBSCRIPTION_PDA_SEED, uuid.as_ref()],
+        bump = subscription.bump
+    )]
+    pub subscription: Account<'info, Subscription>,
+
+	#[account(
+		mut,
+		seeds = [PERMIT_REGISTRY_PDA_SEED, subscription.subscriber.key().as_ref(), mint.key().as_ref()],
+		bump = permit_registry.bump
+	)]
+	pub permit_registry: Account<'info, PermitRegistry>,
+
+	// Only the subscriber can decrease the allowance of their subscription
+    #[account(
+		mut,
+		address = subscription.subscriber @ QBitFlowError::Unauthorized
+	)]
+    pub subscriber: Signer<'info>,
+
+	// The subscriber's token account, approved again with the smaller effective allowance
+    #[account(
+		mut,
+		associated_token::mint = mint,
+		associated_token::authority = subscriber,
+		associated_token::token_program = token_program
+	)]
+    pub subscriber_token_account: InterfaceAccount<'info, TokenAccount>,
+
+	#[account(address = subscription.mint @ QBitFlowError::InvalidSubscriptionParameters)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+	pub token_program: Interface<'info, TokenInterface>,
+}
+
+
+#[derive(Accounts)]
+#[instruction(uuid: [u8; 16])]
+pub struct ProposePriceChange<'info> {
+	#[account()]
+	pub authority_and_owner: AuthorityAndOwner<'info>,
+
+    #[account(
+        mut,
+        seeds = [SUBSCRIPTION_PDA_SEED, uuid.as_ref()],
+        bump = subscription.bump
+    )]
+    pub subscription: Account<'info, Subscription>,
+
+	// Only the merchant of the subscription can propose a price change
+	#[account(address = subscription.merchant @ QBitFlowError::Unauthorized)]
+	pub merchant: Signer<'info>,
+}
+
+
+#[derive(Accounts)]
+#[instruction(uuid: [u8; 16])]
+pub struct AcceptPriceChange<'info> {
+	#[account()]
+	pub authority_and_owner: AuthorityAndOwner<'info>,
+
+	// Fee recipient token account (ATA of the authority.owner)
+    #[account(
+		mut,
+		associated_token::mint = mint,
+		associated_token::authority = authority_and_owner.owner, // fee recipient is authority owner
+		associated_token::token_program = token_program
+	)]
+    pub fee_recipient_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [SUBSCRIPTION_PDA_SEED, uuid.as_ref()],
+        bump = subscription.bump
+    )]
+    pub subscription: Account<'info, Subscription>,
+
+	#[account(
+		mut,
+		seeds = [PERMIT_REGISTRY_PDA_SEED, subscription.subscriber.key().as_ref(), mint.key().as_ref()],
+		bump = permit_registry.bump
+	)]
+	pub permit_registry: Account<'info, PermitRegistry>,
+
+	// Only the subscriber can accept a price change
+    #[account(
+		mut,
+		address = subscription.subscriber @ QBitFlowError::Unauthorized
+	)]
+    pub subscriber: Signer<'info>,
+
+	// The subscriber's token account from which payments are made
+    #[account(
+		mut,
+		associated_token::mint = mint,
+		associated_token::authority = subscriber,
+		associated_token::token_program = token_program
+	)]
+    pub subscriber_token_account: InterfaceAccount<'info, TokenAccount>,
+
+	#[account(address = subscription.mint @ QBitFlowError::InvalidSubscriptionParameters)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+	pub token_program: Interface<'info, TokenInterface>,
+}
+
+
+#[derive(Accounts)]
+#[instruction(uuid: [u8; 16])]
+pub struct MigrateSubscription<'info> {
+	#[account()]
+	pub authority_and_owner: AuthorityAndOwner<'info>,
+
+	/// CHECK: Subscription PDA derived from uuid. It is deserialized manually, since it still uses the legacy layout (without the terms), and is reallocated to the current one
+	#[account(
+		mut,
+		seeds = [SUBSCRIPTION_PDA_SEED, uuid.as_ref()],
+		bump,
+		owner = crate::ID,
+	)]
+	pub subscription: UncheckedAccount<'info>,
+
+	// Token accounts of the subscription (verified against the subscription hash)
+	pub subscriber_token_account: InterfaceAccount<'info, TokenAccount>,
+	pub merchant_token_account: InterfaceAccount<'info, TokenAccount>,
+	pub organization_token_account: InterfaceAccount<'info, TokenAccount>,
+
+	pub system_program: Program<'info, System>,
+}
+
+
+/**
+ * Create a regular subscription
+ * With a trial period, nothing can be charged before the end of the trial (regular subscriptions only)
+ * With a maximum number of payments or an end date, the subscription is completed and closed with its last payment
+ * With a dunning config, failed payments are recorded and retried instead of failing the execution
+ * Emits a SubscriptionCreated event (and a TrialStarted event if the subscription has a trial)
+ */
+#[allow(clippy::too_many_arguments)] // Mirrors the client-facing instruction arguments
+pub fn create_subscription<'info>(
+    ctx: Context<'_, '_, '_, 'info, CreateSubscription<'info>>,
+    uuid: [u8; 16],
+	amount: u64,
+	max_amount: u64,
+    billing_interval: BillingInterval,
+    allowance: u64,
+	compute_refund_params: ComputeRefundData,
+	is_payg: bool,
+	trial_period: u32,
+	dunning_config: Option<DunningConfig>,
+	catch_up_policy: CatchUpPolicy,
+	max_payments: u32,
+	ends_at: i64,
+) -> Result<()> {
+	// let (next_payment_due, remaining_allowance) = _create_subscription(ctx, uuid, amount, max_amount, frequency, allowance, false, compute_refund_params)?;
+	billing_interval.validate()?;
+
+	if max_amount <= amount {
+		return err!(QBitFlowError::InvalidAmount);
+	}
+
+	// Pay-as-you-go subscriptions are billed at the end of each period, there is no trial
+	if is_payg && trial_period > 0 {
+		return err!(QBitFlowError::InvalidSubscriptionParameters);
+	}
+
+	if let Some(config) = dunning_config {
+		if config.max_retries == 0 || config.retry_interval == 0 {
+			return err!(QBitFlowError::InvalidSubscriptionParameters);
+		}
+	}
+
+	if catch_up_policy == CatchUpPolicy::MaxPeriods(0) {
+		return err!(QBitFlowError::InvalidSubscriptionParameters);
+	}
+
+	// Subscribing to a plan: the terms signed by the subscriber must match the plan
+	if let Some(plan) = &ctx.accounts.plan {
+		if !plan.active {
+			return err!(QBitFlowError::PlanInactive);
+		}
+
+		if is_payg
+			|| plan.merchant != ctx.accounts.merchant.key()
+			|| plan.organization != ctx.accounts.organization.key()
+			|| plan.mint != ctx.accounts.mint.key()
+			|| plan.price != amount
+			|| plan.billing_interval != billing_interval
+			|| plan.trial_period != trial_period {
+			return err!(QBitFlowError::InvalidSubscriptionParameters);
+		}
+	}
+
+	// Add allowance to the permit registry
+	let permit_registry = &mut ctx.accounts.permit_registry;
+
+	if permit_registry.bump == 0 {
+		// Newly initialized, set initial values
+		permit_registry.bump = ctx.bumps.permit_registry;
+	}
+
+	// Add allowance entry in permit registry
+	permit_registry.add_allowance(allowance, &ctx.accounts.subscriber, &ctx.accounts.authority_and_owner.authority, &ctx.accounts.token_program, &ctx.accounts.subscriber_token_account, &ctx.accounts.mint)?;
+
+
+	// Now initialize the subscription account
+    let subscription = &mut ctx.accounts.subscription;
+
+
+	let current_time = Clock::get()?.unix_timestamp;
+	let trial_ends_at: i64 = if trial_period > 0 { current_time + trial_period as i64 } else { 0 };
+
+    let next_payment_due: i64 = billing_interval.first_due(is_payg, current_time, trial_ends_at)?;
+
+	// The end date must leave room for at least one payment
+	if ends_at != 0 && ends_at <= next_payment_due {
+		return err!(QBitFlowError::InvalidSubscriptionParameters);
+	}
+
+    
+    subscription.subscriber = ctx.accounts.subscriber.key();
+    subscription.next_payment_due = next_payment_due;
+	subscription.allowance = allowance;
+	subscription.used_allowance = 0;
+	subscription.stopped = false;
+	subscription.max_amount = max_amount;
+    subscription.bump = ctx.bumps.subscription;
+	subscription.last_payment_amount = amount;
+
+	// Store the terms of the subscription, so they can be read from the account (and verified during execution)
+	subscription.version = Subscription::VERSION;
+	subscription.merchant = ctx.accounts.merchant.key();
+	subscription.merchant_token_account = ctx.accounts.merchant_token_account.key();
+	subscription.organization_token_account = ctx.accounts.organization_token_account.key();
+	subscription.mint = ctx.accounts.mint.key();
+	subscription.billing_interval = billing_interval;
+	subscription.is_payg = is_payg;
+	subscription.plan = ctx.accounts.plan.as_ref().map(|plan| plan.key()).unwrap_or_default();
+	subscription.trial_ends_at = trial_ends_at;
+	subscription.status = if trial_period > 0 { SubscriptionStatus::Trialing } else { SubscriptionStatus::Active };
+	subscription.status_before_pause = subscription.status;
+	subscription.dunning_config = dunning_config;
+	subscription.failed_attempts = 0;
+	subscription.retry_at = 0;
+	subscription.catch_up_policy = catch_up_policy;
+	subscription.max_payments = max_payments;
+	subscription.ends_at = ends_at;
+	subscription.payments_made = 0;
+
+
+	// Create the hash of the subscription (kept for compatibility with the legacy accounts, which only store the hash of their terms)
+	subscription.subscription_hash = create_subscription_hash(
+		&ctx.accounts.merchant_token_account.key(),
+		&ctx.accounts.subscriber_token_account.key(), 
+		&billing_interval, 
+		&ctx.accounts.organization_token_account.key()
+	);
+
+	// Compute refund in tokens for the authority owner to refund the compute cost paid in SOL
+	let refund_result = compute_refund(uuid, max_amount - amount, compute_refund_params, ctx.accounts.mint.decimals, CpiContext::new(
+		ctx.accounts.token_program.to_account_info(),
+		TransferChecked {
+			from: ctx.accounts.subscriber_token_account.to_account_info(),
+			mint: ctx.accounts.mint.to_account_info(),
+			to: ctx.accounts.fee_recipient_token_account.to_account_info(),
+			authority: ctx.accounts.subscriber.to_account_info(),
+		},
+	).with_remaining_accounts(ctx.remaining_accounts.to_vec()))?;
+
+
+	// Update the used allowance with the refunded amount (best-effort, if it fails, the authority owner pays the compute cost in SOL)
+	permit_registry.use_allowance(refund_result)?; // And update the permit registry as well (since the total allowance used has increased)
+	subscription.used_allowance = refund_result; // Update the used allowance for the subscription
+
+
+    emit!(SubscriptionCreated {
         uuid,
         next_payment_due,
         initial_allowance: subscription.allowance - subscription.used_allowance,
     });
 
+	if subscription.status == SubscriptionStatus::Trialing {
+		emit!(TrialStarted {
+			uuid,
+			trial_ends_at,
+		});
+	}
+
     Ok(())
 }
 
@@ -423,25 +962,88 @@ pub fn create_subscription(
 /**
  * Execute a payment for a regular subscription
  * This can be called by anyone, but requires the authority.owner signature
+ * For subscriptions to a plan, the plan price is charged (the amount provided is ignored)
  * The permit registry will be updated accordingly
  * Emits a SubscriptionPaymentProcessed event
  */
-pub fn execute_subscription(
-    ctx: Context<ExecuteSubscription>,
+pub fn execute_subscription<'info>(
+    ctx: Context<'_, '_, '_, 'info, ExecuteSubscription<'info>>,
     amount: u64,
     fee_bps: u16,
     uuid: [u8; 16],
-	frequency: u32,
     organization_fee_bps: u16,
 	compute_refund_params: ComputeRefundData,
-	is_payg: bool,
+	transfer_fee_policy: TransferFeePolicy,
 ) -> Result<()> {
 	let subscription = &mut ctx.accounts.subscription;
+
+	// Subscriptions to a plan are billed the plan price
+	let amount = if subscription.plan != Pubkey::default() {
+		match &ctx.accounts.plan {
+			Some(plan) => plan.price,
+			None => return err!(QBitFlowError::InvalidSubscriptionParameters),
+		}
+	} else {
+		amount
+	};
     
+	// Paused and ended subscriptions cannot be billed (canceled pay-as-you-go subscriptions still have their final payment)
+	match subscription.status {
+		SubscriptionStatus::Paused => return err!(QBitFlowError::SubscriptionPaused),
+		SubscriptionStatus::Ended => return err!(QBitFlowError::SubscriptionEnded),
+		_ => {}
+	}
+
 	// Since we're here, the subscription exists (otherwise the PDA derivation would fail)
-    if Clock::get()?.unix_timestamp < subscription.next_payment_due {
+	// After a failed attempt, the payment can only be retried once the retry is due
+    if Clock::get()?.unix_timestamp < subscription.next_payment_due.max(subscription.retry_at) {
         return err!(QBitFlowError::PaymentNotDueYet);
     }
+
+	// A canceled regular subscription is closed at the end of its paid period (nothing is charged)
+	if subscription.status == SubscriptionStatus::PendingCancel && !subscription.is_payg {
+		return end_subscription(ctx.accounts, uuid, CancellationReason::Subscriber);
+	}
+
+	// From its effective date, an accepted price change applies its max amount, and a price change the subscriber did not accept cancels the subscription (nothing is charged)
+	if let Some(price_change) = subscription.pending_price_change {
+		if Clock::get()?.unix_timestamp >= price_change.effective_at {
+			if price_change.accepted_max_amount == 0 {
+				return end_subscription(ctx.accounts, uuid, CancellationReason::PriceChangeNotAccepted);
+			}
+
+			subscription.max_amount = price_change.accepted_max_amount;
+			subscription.pending_price_change = None;
+
+			emit!(MaxAmountUpdated {
+				uuid,
+				new_max_amount: price_change.accepted_max_amount,
+			});
+		}
+	}
+
+	// Missed periods skipped by the catch-up policy (regular subscriptions only, the billing of pay-as-you-go subscriptions never falls behind)
+	let skipped_periods: u64 = if subscription.is_payg || subscription.catch_up_policy == CatchUpPolicy::ChargeAll {
+		0
+	} else {
+		let periods_owed = subscription.billing_interval.periods_due(subscription.next_payment_due, Clock::get()?.unix_timestamp)?;
+		subscription.catch_up_policy.periods_to_skip(periods_owed)
+	};
+
+	// A fixed-term regular subscription has no payment due at or after its end date: it is completed without charging
+	// (the period charged can reach it when the payment is executed late and missed periods are skipped)
+	// Pay-as-you-go subscriptions are billed at the end of the period, so their final period is still billed (and the subscription completed after it)
+	if !subscription.is_payg && subscription.ends_at != 0 && subscription.billing_interval.advance(subscription.next_payment_due, skipped_periods)? >= subscription.ends_at {
+		let payments_made = subscription.payments_made;
+		close_subscription(ctx.accounts, uuid)?;
+
+		emit!(SubscriptionCompleted {
+			uuid,
+			payments_made,
+		});
+
+		return Ok(());
+	}
     
     if amount == 0 {
         return err!(QBitFlowError::ZeroAmount);
@@ -450,176 +1052,420 @@ pub fn execute_subscription(
 		return err!(QBitFlowError::MaxAmountExceeded);
 	}
 
-	// Create the hash from the arguments provided, and ensure it matches the stored hash
-	let computed_hash = create_subscription_hash(
-		&ctx.accounts.merchant_token_account.key(),
-		&ctx.accounts.subscriber_token_account.key(),
-		frequency,
-		&ctx.accounts.organization_token_account.key()
-	);
+	// The merchant, organization and mint are verified against the terms stored in the subscription (account constraints)
 
-	// Ensure the parameters are correct by comparing the hashes
-	if subscription.subscription_hash != computed_hash {
-		return err!(QBitFlowError::InvalidSubscriptionParameters);
-	}
+	// The credit left by a plan downgrade (if any) pays for part of the amount
+	let credit_applied = amount.min(subscription.credit);
+
+	let settlement = Settlement::compute(&ctx.accounts.mint.to_account_info(), amount - credit_applied, fee_bps, organization_fee_bps, transfer_fee_policy)?;
+
+	// Total amount debited from the subscriber (can exceed `amount` if the subscriber covers the transfer fee)
+	let total_debited = settlement.total_debited()?;
     
 	// Ensure the subscription has enough allowance left
-    if subscription.used_allowance + amount >= subscription.allowance {
+    if subscription.used_allowance + total_debited >= subscription.allowance {
         return err!(QBitFlowError::InsufficientAllowance);
     }
 
 	let permit_registry = &mut ctx.accounts.permit_registry;
 
 	// Ensure the global permit registry has enough allowance left
-	if !permit_registry.has_enough_allowance(amount) {
+	if !permit_registry.has_enough_allowance(total_debited) {
 		return err!(QBitFlowError::InsufficientAllowance);
 	}
 
-    
-	let (fee_amount, org_fee_amount) = calculate_fee(amount, fee_bps, organization_fee_bps)?;
-    let remaining_amount = amount
-        .checked_sub(fee_amount)
-        .and_then(|x| x.checked_sub(org_fee_amount))
-        .ok_or(QBitFlowError::Overflow)?;
-
-
-    // Transfer fee to fee recipient
-    token::transfer(
-        CpiContext::new_with_signer(
-            ctx.accounts.token_program.to_account_info(),
-            Transfer {
-                from: ctx.accounts.subscriber_token_account.to_account_info(),
-                to: ctx.accounts.fee_recipient_token_account.to_account_info(),
-                authority: ctx.accounts.authority_and_owner.authority.to_account_info(), // Program's delegate PDA
-            },
-            &[&ctx.accounts.authority_and_owner.authority.get_seeds()]
-        ),
-        fee_amount, 
-    )?;
-
-    // Transfer organization fee if applicable
-    if org_fee_amount > 0 {
-        token::transfer(
-            CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.subscriber_token_account.to_account_info(),
-                    to: ctx.accounts.organization_token_account.to_account_info(),
-                    authority: ctx.accounts.authority_and_owner.authority.to_account_info(),
-                },
-				&[&ctx.accounts.authority_and_owner.authority.get_seeds()]
-            ),
-            org_fee_amount,
-        )?;
-    }
+	// With dunning, a payment the subscriber's token account cannot cover is recorded as a failed attempt (instead of failing the transaction)
+	if let (Some(dunning_config), true) = (subscription.dunning_config, total_debited > 0) {
+		if let Some(reason) = check_payment_failure(&ctx.accounts.subscriber_token_account, &ctx.accounts.authority_and_owner.authority.key(), total_debited) {
+			return record_failed_payment(ctx.accounts, uuid, dunning_config, reason);
+		}
+	}
+
 
-    // Transfer remaining amount to merchant
-    token::transfer(
-        CpiContext::new_with_signer(
-            ctx.accounts.token_program.to_account_info(),
-            Transfer {
-                from: ctx.accounts.subscriber_token_account.to_account_info(),
-                to: ctx.accounts.merchant_token_account.to_account_info(),
-                authority: ctx.accounts.authority_and_owner.authority.to_account_info(),
-            },
-			&[&ctx.accounts.authority_and_owner.authority.get_seeds()]
-        ),
-        remaining_amount,
-    )?;
+	// Transfer the fees and the remaining amount to the merchant (via PDA delegation)
+	settlement.transfer(
+		&ctx.accounts.token_program,
+		&ctx.accounts.subscriber_token_account,
+		&ctx.accounts.mint,
+		&ctx.accounts.fee_recipient_token_account,
+		&ctx.accounts.organization_token_account,
+		&ctx.accounts.merchant_token_account,
+		&ctx.accounts.authority_and_owner.authority,
+		ctx.remaining_accounts,
+	)?;
 	
 	// Get the allowance from the registry
 	let permit_registry = &mut ctx.accounts.permit_registry;
 
 	subscription.last_payment_amount = amount;
+	subscription.credit -= credit_applied;
 
 	// Compute refund in tokens for the authority owner to refund the compute cost paid in SOL
 	// Authority of the transfer is the authority PDA (delegate)
-	let refund_result = compute_refund(uuid, subscription.max_amount - amount, compute_refund_params, CpiContext::new_with_signer(
+	let refund_result = compute_refund(uuid, subscription.max_amount - amount, compute_refund_params, ctx.accounts.mint.decimals, CpiContext::new_with_signer(
 		ctx.accounts.token_program.to_account_info(),
-		Transfer {
+		TransferChecked {
 			from: ctx.accounts.subscriber_token_account.to_account_info(),
+			mint: ctx.accounts.mint.to_account_info(),
 			to: ctx.accounts.fee_recipient_token_account.to_account_info(),
 			authority: ctx.accounts.authority_and_owner.authority.to_account_info(),
 		},
 		&[&ctx.accounts.authority_and_owner.authority.get_seeds()]
-	))?;
+	).with_remaining_accounts(ctx.remaining_accounts.to_vec()))?;
 
 
-	// Update the total amount to include the refund
-	// Update the total used in the permit registry
-	permit_registry.use_allowance(amount + refund_result)?; // Increase the global used amount
+	// Record the charge, refund included, in the used allowance of the subscription and of the permit registry
+	use_allowance(subscription, permit_registry, total_debited + refund_result)?;
 
-    // Update subscription
-    subscription.used_allowance += amount + refund_result; // Increase the used allowance (including the refund amount)
+	// End of the usage period billed (pay-as-you-go subscriptions)
+	let billed_period_end = subscription.next_payment_due;
 
-	let next_payment_due: i64;
-	if !is_payg {
-		// For regular subscriptions, move the next payment due forward by frequency
-		next_payment_due = subscription.next_payment_due + frequency as i64;
+	let next_payment_due: i64 = if !subscription.is_payg {
+		// For regular subscriptions, move the next payment due forward by one period (plus the periods skipped by the catch-up policy)
+		subscription.period_start = subscription.billing_interval.advance(subscription.next_payment_due, skipped_periods)?;
+		subscription.billing_interval.next_due(subscription.period_start)?
 	} else {
-		// For pay-as-you-go subscriptions, set the next payment due to now + frequency (since the backend might skip some calls if the usage is low to save compute)
+		// For pay-as-you-go subscriptions, set the next payment due to now + one period (since the backend might skip some calls if the usage is low to save compute)
 		// This ensures the next payment due is always in the future
 		// And the billing is done at the end of the period
 		// We decrease by one hour to avoid pushing the next billing date a day each time (since the backend executes every 24 hours, therefore if we add 24 hours each time, the next payment due will be pushed by one day each time)
-		next_payment_due = Clock::get()?.unix_timestamp + frequency as i64 - 3600;
-	}
+		subscription.period_start = Clock::get()?.unix_timestamp;
+		let next_payment_due = subscription.billing_interval.next_due(subscription.period_start)? - 3600;
+
+		// The final period of a fixed-term subscription is billed at its end date
+		if subscription.ends_at != 0 { next_payment_due.min(subscription.ends_at) } else { next_payment_due }
+	};
 	subscription.next_payment_due = next_payment_due;
 
-	
+	// Reset the failed attempts
+	subscription.failed_attempts = 0;
+	subscription.retry_at = 0;
+
+	// The first payment after the trial converts the subscription to a paid one
+	// Checked on the payments made rather than the status, since a failed first charge moves the subscription from Trialing to PastDue
+	if subscription.payments_made == 0 && subscription.trial_ends_at != 0 {
+		emit!(TrialConverted {
+			uuid,
+			amount,
+		});
+	}
+
+	subscription.payments_made = subscription.payments_made.saturating_add(1);
+	let payments_made = subscription.payments_made;
+
+	// Fixed-term subscriptions are completed with their last payment
+	// (for pay-as-you-go subscriptions, the payment of the period ending at the end date)
+	let period_end = if subscription.is_payg { billed_period_end } else { next_payment_due };
+	let completed = (subscription.max_payments != 0 && payments_made >= subscription.max_payments)
+		|| (subscription.ends_at != 0 && period_end >= subscription.ends_at);
+
+	// A canceled pay-as-you-go subscription is closed after its final payment
+	let canceled = subscription.status == SubscriptionStatus::PendingCancel;
 
 	let remaining_allowance: u64;
 
-	if subscription.stopped {
-		// If the subscription is stopped, revoke the allowance in the permit registry (must be a pay-as-you-go subscription)
+	if canceled || completed {
+		// Final payment (of a canceled pay-as-you-go subscription, or of a fixed-term subscription), revoke the allowance in the permit registry
 		permit_registry.revoke_allowance(subscription)?;
 		remaining_allowance = 0;
-		// Close the subscription account by setting the close constraint to the subscriber
-
-		if permit_registry.total_allowance == 0 {
-			// If the permit registry has no more allowance, close it as well
-		}
+		subscription.set_status(uuid, SubscriptionStatus::Ended)?;
 	} else {
 		remaining_allowance = subscription.allowance - subscription.used_allowance;
+		subscription.set_status(uuid, SubscriptionStatus::Active)?;
 	}
 
     emit!(SubscriptionPaymentProcessed {
         uuid,
-        next_payment_due: next_payment_due,
-        remaining_allowance: remaining_allowance,
+        next_payment_due,
+        remaining_allowance,
+		amount,
+		// Net amounts actually received by each party (after the Token-2022 transfer fee, if any)
+		merchant_amount: settlement.merchant.net,
+		fee_amount: settlement.fee.net,
+		organization_fee_amount: settlement.organization_fee.net,
+		catch_up_policy: subscription.catch_up_policy,
+		skipped_periods,
+		credit_applied,
     });
 
+	if canceled || completed {
+		// Close the subscription account, and the permit registry if it has no more allowance
+		ctx.accounts.subscription.close(ctx.accounts.authority_and_owner.owner.to_account_info())?;
+
+		if permit_registry.total_allowance == 0 {
+			permit_registry.close(ctx.accounts.authority_and_owner.owner.to_account_info())?;
+		}
+
+		if canceled {
+			emit!(SubscriptionCancelled {
+				uuid,
+				reason: CancellationReason::Subscriber,
+			});
+		} else {
+			emit!(SubscriptionCompleted {
+				uuid,
+				payments_made,
+			});
+		}
+	}
+
     Ok(())
 }
 
 
-/**
- * Cancel a subscription (regular subscription only)
- * This can be called by the subscriber, and requires their signature
- * The subscription can only be canceled if the nextPaymentDue is in the future (i.e. before the next payment is due)
- * The permit registry will be updated accordingly
- */
-pub fn cancel_subscription(
-    ctx: Context<CancelSubscription>,
-    _uuid: [u8; 16],
-	is_payg: bool,
-) -> Result<()> {
+// Record a charge (including its compute refund) in the used allowance of the subscription and of the permit registry
+// Fails if either has not enough allowance left (the whole transaction is reverted, the transfers included)
+// Shared by every subscription charge, so the registry accounting is the same on each path
+fn use_allowance(subscription: &mut Subscription, permit_registry: &mut PermitRegistry, amount: u64) -> Result<()> {
+	if amount == 0 {
+		return Ok(());
+	}
+
+	let used_allowance = subscription.used_allowance.checked_add(amount).ok_or(QBitFlowError::Overflow)?;
+	if used_allowance >= subscription.allowance {
+		return err!(QBitFlowError::InsufficientAllowance);
+	}
+
+	permit_registry.use_allowance(amount)?;
+	subscription.used_allowance = used_allowance;
+
+	Ok(())
+}
+
+
+// Split of a subscription charge between the fee recipient, the organization and the merchant
+// Shared by execute_subscription and change_subscription_plan, so every subscription charge goes through the same fee path
+#[derive(Clone, Copy, Default)]
+struct Settlement {
+	fee: TransferAmounts,
+	organization_fee: TransferAmounts,
+	merchant: TransferAmounts,
+}
+
+impl Settlement {
+	// Compute the split of a charge of `amount`, with the Token-2022 transfer fee (if any) applied to each transfer according to the policy
+	// A zero amount settles nothing
+	fn compute(mint: &AccountInfo, amount: u64, fee_bps: u16, organization_fee_bps: u16, transfer_fee_policy: TransferFeePolicy) -> Result<Self> {
+		if amount == 0 {
+			return Ok(Settlement::default());
+		}
+
+		let (fee_amount, org_fee_amount) = calculate_fee(amount, fee_bps, organization_fee_bps)?;
+		let remaining_amount = amount
+			.checked_sub(fee_amount)
+			.and_then(|x| x.checked_sub(org_fee_amount))
+			.ok_or(QBitFlowError::Overflow)?;
+
+		Ok(Settlement {
+			fee: TransferAmounts::compute(mint, fee_amount, transfer_fee_policy)?,
+			organization_fee: TransferAmounts::compute(mint, org_fee_amount, transfer_fee_policy)?,
+			merchant: TransferAmounts::compute(mint, remaining_amount, transfer_fee_policy)?,
+		})
+	}
+
+	// Total amount debited from the subscriber
+	fn total_debited(&self) -> Result<u64> {
+		self.fee.gross
+			.checked_add(self.organization_fee.gross)
+			.and_then(|x| x.checked_add(self.merchant.gross))
+			.ok_or(QBitFlowError::Overflow.into())
+	}
+
+	// Transfer the charge from the subscriber's token account, signed by the authority PDA (delegate)
+	#[inline(never)]
+	#[allow(clippy::too_many_arguments)]
+	fn transfer<'info>(
+		&self,
+		token_program: &Interface<'info, TokenInterface>,
+		subscriber_token_account: &InterfaceAccount<'info, TokenAccount>,
+		mint: &InterfaceAccount<'info, Mint>,
+		fee_recipient_token_account: &InterfaceAccount<'info, TokenAccount>,
+		organization_token_account: &InterfaceAccount<'info, TokenAccount>,
+		merchant_token_account: &InterfaceAccount<'info, TokenAccount>,
+		authority: &Account<'info, Authority>,
+		remaining_accounts: &[AccountInfo<'info>],
+	) -> Result<()> {
+		let transfers = [
+			(self.fee.gross, fee_recipient_token_account),
+			(self.organization_fee.gross, organization_token_account),
+			(self.merchant.gross, merchant_token_account),
+		];
+
+		for (amount, to) in transfers {
+			if amount == 0 {
+				continue;
+			}
+
+			transfer_checked_with_hook(
+				CpiContext::new_with_signer(
+					token_program.to_account_info(),
+					TransferChecked {
+						from: subscriber_token_account.to_account_info(),
+						mint: mint.to_account_info(),
+						to: to.to_account_info(),
+						authority: authority.to_account_info(), // Program's delegate PDA
+					},
+					&[&authority.get_seeds()]
+				).with_remaining_accounts(remaining_accounts.to_vec()),
+				amount,
+				mint.decimals,
+			)?;
+		}
+
+		Ok(())
+	}
+}
+
+
+// Check whether the subscriber's token account can cover a payment of `amount` made by the authority PDA (delegate)
+// Returns the reason the payment would fail, if any
+fn check_payment_failure(subscriber_token_account: &TokenAccount, authority: &Pubkey, amount: u64) -> Option<PaymentFailureReason> {
+	if subscriber_token_account.is_frozen() {
+		return Some(PaymentFailureReason::AccountFrozen);
+	}
+
+	if subscriber_token_account.delegate != COption::Some(*authority) {
+		return Some(PaymentFailureReason::DelegateRevoked);
+	}
+
+	if subscriber_token_account.delegated_amount < amount {
+		return Some(PaymentFailureReason::InsufficientDelegation);
+	}
+
+	if subscriber_token_account.amount < amount {
+		return Some(PaymentFailureReason::InsufficientBalance);
+	}
+
+	None
+}
+
+
+// Record a failed payment attempt of a subscription with dunning
+// The subscription becomes PastDue and the next attempt is scheduled with an exponential backoff, or it is canceled once max_retries is exceeded
+#[inline(never)]
+fn record_failed_payment(
+	accounts: &mut ExecuteSubscription,
+	uuid: [u8; 16],
+	dunning_config: DunningConfig,
+	reason: PaymentFailureReason,
+) -> Result<()> {
+	let subscription = &mut accounts.subscription;
+	subscription.failed_attempts = subscription.failed_attempts.saturating_add(1);
+	let failed_attempts = subscription.failed_attempts;
+
+	if dunning_config.exhausted(failed_attempts) {
+		// Too many failed attempts, cancel the subscription
+		emit!(SubscriptionPaymentFailed {
+			uuid,
+			reason,
+			failed_attempts,
+			next_retry_at: 0,
+		});
+
+		return end_subscription(accounts, uuid, CancellationReason::PaymentFailed);
+	}
+
+	let next_retry_at = Clock::get()?.unix_timestamp.saturating_add(dunning_config.retry_delay(failed_attempts));
+	subscription.retry_at = next_retry_at;
+
+	// Canceled pay-as-you-go subscriptions stay PendingCancel until their final payment succeeds
+	if subscription.status != SubscriptionStatus::PendingCancel {
+		subscription.set_status(uuid, SubscriptionStatus::PastDue)?;
+	}
+
+	emit!(SubscriptionPaymentFailed {
+		uuid,
+		reason,
+		failed_attempts,
+		next_retry_at,
+	});
+
+	Ok(())
+}
+
+
+// Cancel a subscription during its execution (without charging it), and emit a SubscriptionCancelled event
+#[inline(never)]
+fn end_subscription(accounts: &mut ExecuteSubscription, uuid: [u8; 16], reason: CancellationReason) -> Result<()> {
+	close_subscription(accounts, uuid)?;
+
+	emit!(SubscriptionCancelled {
+		uuid,
+		reason,
+	});
+
+	Ok(())
+}
+
+
+// End a subscription during its execution (without charging it)
+// Revokes its allowance, and closes the subscription account (and the permit registry if it has no more allowance)
+#[inline(never)]
+fn close_subscription(accounts: &mut ExecuteSubscription, uuid: [u8; 16]) -> Result<()> {
+	let subscription = &mut accounts.subscription;
+	subscription.set_status(uuid, SubscriptionStatus::Ended)?;
+
+	let permit_registry = &mut accounts.permit_registry;
+	permit_registry.revoke_allowance(subscription)?;
+
+	accounts.subscription.close(accounts.authority_and_owner.owner.to_account_info())?;
+
+	if permit_registry.total_allowance == 0 {
+		// If the permit registry has no more allowance, close it as well
+		permit_registry.close(accounts.authority_and_owner.owner.to_account_info())?;
+	}
+
+	Ok(())
+}
+
+
+/**
+ * Cancel a subscription
+ * This can be called by the subscriber at any time, and requires their signature
+ * Regular subscriptions with a paid period in progress move to PendingCancel: no further payment is charged, the allowance is released from the permit registry right away,
+ * and the subscription stays accessible until the end of the paid period (it is closed by close_canceled_subscription or its execution once nextPaymentDue is reached)
+ * Subscriptions with no paid period in progress (in trial, paused, never charged, or past their nextPaymentDue) are closed immediately
+ * Pay-as-you-go subscriptions move to PendingCancel and are closed after their final payment
+ * Canceling a subscription already pending cancellation has no effect
+ * The merchant keeps what was already paid
+ */
+pub fn cancel_subscription(
+    ctx: Context<CancelSubscription>,
+    _uuid: [u8; 16],
+) -> Result<()> {
     let subscription = &mut ctx.accounts.subscription;
 
-	if is_payg {
-		// Set the stopped flag to true
-		subscription.stopped = true;
+	// Already canceled, nothing left to do
+	if subscription.status == SubscriptionStatus::PendingCancel {
 		return Ok(());
 	}
 
-	// Ensure the nextPaymentDue is in the future
-	let current_time = Clock::get()?.unix_timestamp;
-	if current_time >= subscription.next_payment_due {
-		return err!(QBitFlowError::CannotCancelActiveSubscription);
+	if subscription.is_payg {
+		// The subscription waits for its final payment, submitted by the merchant (settle_payg_subscription) or billed at the end of the period
+		subscription.paused_at = 0;
+		subscription.set_status(_uuid, SubscriptionStatus::PendingCancel)?;
+		return Ok(());
 	}
 
 	// Revoke the allowance from the permit registry
 	let permit_registry = &mut ctx.accounts.permit_registry;
 	permit_registry.revoke_allowance(subscription)?;
+
+	// The period in progress was paid for (nothing has been charged during the trial, and paused subscriptions cannot be billed)
+	// Calendar subscriptions start Active before their first charge (due on the anchor date), so a payment must have been made
+	let paid_period = subscription.status == SubscriptionStatus::Active
+		&& subscription.payments_made > 0
+		&& Clock::get()?.unix_timestamp < subscription.next_payment_due;
+
+	if paid_period {
+		// Keep the subscription until the end of the paid period, with its allowance released
+		subscription.allowance = 0;
+		subscription.used_allowance = 0;
+		subscription.set_status(_uuid, SubscriptionStatus::PendingCancel)?;
+		return Ok(());
+	}
+
+	subscription.set_status(_uuid, SubscriptionStatus::Ended)?;
     
 
 	// Close the subscription account 
@@ -632,46 +1478,453 @@ pub fn cancel_subscription(
 
 	emit!(SubscriptionCancelled {
 		uuid: _uuid,
+		reason: CancellationReason::Subscriber,
+	});
+
+    // The account will be closed automatically due to the close constraint
+    Ok(())
+}
+
+
+/**
+ * Close a canceled regular subscription at the end of its paid period (nothing is charged)
+ * This can be called by the authority owner once the paid period is over
+ * Its allowance was released when it was canceled, so the permit registry is not required: the subscription can be closed even if the registry was closed in the meantime
+ */
+pub fn close_canceled_subscription(
+	ctx: Context<CloseCanceledSubscription>,
+	uuid: [u8; 16]
+) -> Result<()> {
+	let subscription = &mut ctx.accounts.subscription;
+
+	// Canceled pay-as-you-go subscriptions still hold their allowance, and are closed by settle_payg_subscription or execute_subscription
+	if subscription.status != SubscriptionStatus::PendingCancel || subscription.is_payg {
+		return err!(QBitFlowError::SubscriptionNotCanceled);
+	}
+
+	if Clock::get()?.unix_timestamp < subscription.next_payment_due {
+		return err!(QBitFlowError::PaymentNotDueYet);
+	}
+
+	subscription.set_status(uuid, SubscriptionStatus::Ended)?;
+
+	ctx.accounts.subscription.close(ctx.accounts.authority_and_owner.owner.to_account_info())?;
+
+	if let Some(permit_registry) = &mut ctx.accounts.permit_registry {
+		if permit_registry.total_allowance == 0 {
+			// If the permit registry has no more allowance, close it as well
+			permit_registry.close(ctx.accounts.authority_and_owner.owner.to_account_info())?;
+		}
+	}
+
+	emit!(SubscriptionCancelled {
+		uuid,
+		reason: CancellationReason::Subscriber,
 	});
 
-    // The account will be closed automatically due to the close constraint
-    Ok(())
-}
+	Ok(())
+}
+
+
+/**
+ * Cancel a subscription on the merchant's side (e.g. discontinued product, banned user)
+ * This can be called by the merchant of the subscription, and requires their signature
+ * The subscription is canceled immediately, without time checks: its allowance is revoked, and the account closed
+ * Pending pay-as-you-go usage is not charged (the merchant can use settle_payg_subscription once the subscriber canceled instead)
+ */
+pub fn merchant_cancel_subscription(
+	ctx: Context<MerchantCancelSubscription>,
+	uuid: [u8; 16]
+) -> Result<()> {
+	let subscription = &mut ctx.accounts.subscription;
+	subscription.set_status(uuid, SubscriptionStatus::Ended)?;
+
+	// Revoke the allowance from the permit registry
+	let permit_registry = &mut ctx.accounts.permit_registry;
+	permit_registry.revoke_allowance(subscription)?;
+
+	ctx.accounts.subscription.close(ctx.accounts.authority_and_owner.owner.to_account_info())?;
+
+	if permit_registry.total_allowance == 0 {
+		// If the permit registry has no more allowance, close it as well
+		permit_registry.close(ctx.accounts.authority_and_owner.owner.to_account_info())?;
+	}
+
+	emit!(SubscriptionCancelled {
+		uuid,
+		reason: CancellationReason::Merchant,
+	});
+
+	Ok(())
+}
+
+
+/**
+ * Settle a canceled pay-as-you-go subscription
+ * This can be called by the merchant of the subscription, and requires their signature
+ * The merchant submits the final usage charge (capped by max_amount, can be 0 if there was no usage), which is transferred through the same fee path as execute_subscription
+ * The subscription is then closed (the rent is returned to the authority owner), its allowance revoked, and the permit registry closed if it has no more allowance
+ * Emits a PaygSubscriptionSettled event and a SubscriptionCancelled event
+ */
+pub fn settle_payg_subscription<'info>(
+	ctx: Context<'_, '_, '_, 'info, SettlePaygSubscription<'info>>,
+	uuid: [u8; 16],
+	amount: u64,
+	fee_bps: u16,
+	organization_fee_bps: u16,
+	compute_refund_params: ComputeRefundData,
+	transfer_fee_policy: TransferFeePolicy,
+) -> Result<()> {
+	let subscription = &mut ctx.accounts.subscription;
+
+	if !subscription.is_payg {
+		return err!(QBitFlowError::InvalidSubscriptionParameters);
+	}
+
+	// The subscriber must have canceled the subscription first
+	if subscription.status != SubscriptionStatus::PendingCancel {
+		return err!(QBitFlowError::SubscriptionNotCanceled);
+	}
+
+	if amount >= subscription.max_amount {
+		return err!(QBitFlowError::MaxAmountExceeded);
+	}
+
+	let settlement = Settlement::compute(&ctx.accounts.mint.to_account_info(), amount, fee_bps, organization_fee_bps, transfer_fee_policy)?;
+	let total_debited = settlement.total_debited()?;
+
+	let permit_registry = &mut ctx.accounts.permit_registry;
+
+	// Ensure the subscription and the global permit registry have enough allowance left
+	if total_debited > 0 && (subscription.used_allowance + total_debited >= subscription.allowance || !permit_registry.has_enough_allowance(total_debited)) {
+		return err!(QBitFlowError::InsufficientAllowance);
+	}
+
+	settlement.transfer(
+		&ctx.accounts.token_program,
+		&ctx.accounts.subscriber_token_account,
+		&ctx.accounts.mint,
+		&ctx.accounts.fee_recipient_token_account,
+		&ctx.accounts.organization_token_account,
+		&ctx.accounts.merchant_token_account,
+		&ctx.accounts.authority_and_owner.authority,
+		ctx.remaining_accounts,
+	)?;
+
+	// Compute refund in tokens for the authority owner to refund the compute cost paid in SOL
+	// Authority of the transfer is the authority PDA (delegate)
+	// Like execute_subscription, parameter errors are propagated (transfer failures are handled by compute_refund)
+	let refund_result = compute_refund(uuid, subscription.max_amount - amount, compute_refund_params, ctx.accounts.mint.decimals, CpiContext::new_with_signer(
+		ctx.accounts.token_program.to_account_info(),
+		TransferChecked {
+			from: ctx.accounts.subscriber_token_account.to_account_info(),
+			mint: ctx.accounts.mint.to_account_info(),
+			to: ctx.accounts.fee_recipient_token_account.to_account_info(),
+			authority: ctx.accounts.authority_and_owner.authority.to_account_info(),
+		},
+		&[&ctx.accounts.authority_and_owner.authority.get_seeds()]
+	).with_remaining_accounts(ctx.remaining_accounts.to_vec()))?;
+
+	// Record the final charge, refund included, like execute_subscription (revoked right after, with the rest of the allowance)
+	use_allowance(subscription, permit_registry, total_debited + refund_result)?;
+
+	subscription.last_payment_amount = amount;
+	subscription.set_status(uuid, SubscriptionStatus::Ended)?;
+
+	// Revoke the allowance from the permit registry (the final payment included)
+	permit_registry.revoke_allowance(subscription)?;
+
+	emit!(PaygSubscriptionSettled {
+		uuid,
+		amount,
+		merchant_amount: settlement.merchant.net,
+		fee_amount: settlement.fee.net,
+		organization_fee_amount: settlement.organization_fee.net,
+	});
+
+	// Close the subscription account, and the permit registry if it has no more allowance
+	ctx.accounts.subscription.close(ctx.accounts.authority_and_owner.owner.to_account_info())?;
+
+	if permit_registry.total_allowance == 0 {
+		permit_registry.close(ctx.accounts.authority_and_owner.owner.to_account_info())?;
+	}
+
+	emit!(SubscriptionCancelled {
+		uuid,
+		reason: CancellationReason::Subscriber,
+	});
+
+	Ok(())
+}
+
+
+/**
+ * Force cancel a subscription
+ * Can only be called by the authority owner
+ * Unlike regular cancel, this doesn't need the signature of the subscriber, and does not perform time checks
+ * This is useful for admin purposes, or if the subscriber has lost access to their account
+ */
+pub fn force_cancel_subscription(
+	ctx: Context<ForceCancelSubscription>,
+	_uuid: [u8; 16]
+) -> Result<()> {
+	let subscription = &mut ctx.accounts.subscription;
+	subscription.set_status(_uuid, SubscriptionStatus::Ended)?;
+
+	// Revoke the allowance from the permit registry
+	let permit_registry = &mut ctx.accounts.permit_registry;
+	permit_registry.revoke_allowance(subscription)?;
+
+	// Close the subscription account by setting the close constraint to the subscriber
+	ctx.accounts.subscription.close(ctx.accounts.authority_and_owner.owner.to_account_info())?;
+
+	if permit_registry.total_allowance == 0 {
+		// If the permit registry has no more allowance, close it as well
+		permit_registry.close(ctx.accounts.authority_and_owner.owner.to_account_info())?;
+	}
+
+	emit!(SubscriptionCancelled {
+		uuid: _uuid,
+		reason: CancellationReason::Admin,
+	});
+
+    // The account will be closed automatically due to the close constraint
+    Ok(())
+}
+
+
+
+
+/**
+ * Pause a subscription
+ * This can be called by the subscriber or the merchant, and requires their signature
+ * Billing is frozen until the subscription is resumed, the allowance stays reserved in the permit registry
+ * Emits a SubscriptionPaused event
+ */
+pub fn pause_subscription(
+	ctx: Context<PauseSubscription>,
+	uuid: [u8; 16],
+) -> Result<()> {
+	let subscription = &mut ctx.accounts.subscription;
+
+	let signer = ctx.accounts.signer.key();
+	if signer != subscription.subscriber && signer != subscription.merchant {
+		return err!(QBitFlowError::Unauthorized);
+	}
+
+	if subscription.status == SubscriptionStatus::Paused {
+		return err!(QBitFlowError::SubscriptionPaused);
+	}
+
+	// Only trialing, active and past due subscriptions can be paused (enforced by the status transition)
+	let status_before_pause = subscription.status;
+	subscription.set_status(uuid, SubscriptionStatus::Paused)?;
+	subscription.status_before_pause = status_before_pause;
+
+	let paused_at = Clock::get()?.unix_timestamp;
+	subscription.paused_at = paused_at;
+
+	emit!(SubscriptionPaused {
+		uuid,
+		paused_at,
+	});
+
+	Ok(())
+}
+
+
+/**
+ * Resume a paused subscription
+ * This can be called by the subscriber or the merchant, and requires their signature
+ * The next payment due (and the end of the trial, if any) is moved forward by the time spent paused (then to the next anchor date for calendar intervals)
+ * Emits a SubscriptionResumed event
+ */
+pub fn resume_subscription(
+	ctx: Context<ResumeSubscription>,
+	uuid: [u8; 16],
+) -> Result<()> {
+	let subscription = &mut ctx.accounts.subscription;
+
+	let signer = ctx.accounts.signer.key();
+	if signer != subscription.subscriber && signer != subscription.merchant {
+		return err!(QBitFlowError::Unauthorized);
+	}
+
+	if subscription.status != SubscriptionStatus::Paused {
+		return err!(QBitFlowError::SubscriptionNotPaused);
+	}
+
+	let paused_duration = Clock::get()?.unix_timestamp.saturating_sub(subscription.paused_at);
+
+	subscription.shift_schedule(paused_duration)?;
+	subscription.paused_at = 0;
+
+	// Back to the status the subscription was paused from
+	let status_before_pause = subscription.status_before_pause;
+	subscription.set_status(uuid, status_before_pause)?;
+
+	emit!(SubscriptionResumed {
+		uuid,
+		next_payment_due: subscription.next_payment_due,
+	});
+
+	Ok(())
+}
+
+
+/**
+ * Change the plan of a subscription (upgrade or downgrade), keeping its uuid and history
+ * This can be called by the subscriber, and requires their signature
+ * The schedule is realigned on the anchor of the new billing interval: the next payment is due on its first anchor date at or after the current due date
+ * The time until then is prorated: the new plan price is charged for it, and the unused part of the last payment is credited
+ * An upgrade is charged immediately (through the same fee path as execute_subscription), a downgrade leaves a credit deducted from the next payments
+ * During the trial, or if no payment was recorded yet (period_start of 0), nothing is prorated, and the new price simply applies from the next payment
+ * Emits a SubscriptionPlanChanged event
+ */
+pub fn change_subscription_plan<'info>(
+	ctx: Context<'_, '_, '_, 'info, ChangeSubscriptionPlan<'info>>,
+	uuid: [u8; 16],
+	new_max_amount: u64,
+	fee_bps: u16,
+	organization_fee_bps: u16,
+	compute_refund_params: ComputeRefundData,
+	transfer_fee_policy: TransferFeePolicy,
+) -> Result<()> {
+	let subscription = &mut ctx.accounts.subscription;
+	let new_plan = &ctx.accounts.new_plan;
+
+	// Pay-as-you-go subscriptions are billed for their usage, not a plan price
+	if subscription.is_payg || new_plan.key() == subscription.plan {
+		return err!(QBitFlowError::InvalidSubscriptionParameters);
+	}
+
+	if !new_plan.active {
+		return err!(QBitFlowError::PlanInactive);
+	}
+
+	if new_max_amount <= new_plan.price {
+		return err!(QBitFlowError::MaxAmountInvalid);
+	}
+
+	let current_time = Clock::get()?.unix_timestamp;
+
+	let new_interval = new_plan.billing_interval;
+
+	// Prorated amounts, and the new schedule (next payment due, and start of the period it closes)
+	let (prorated_charge, prorated_credit, next_payment_due, period_start) = match subscription.status {
+		// Nothing has been paid yet: the first payment is due on the first anchor date of the new interval after the trial
+		SubscriptionStatus::Trialing => (0, 0, new_interval.align(subscription.trial_ends_at), 0),
+		// No payment recorded (never executed, or migrated): nothing to prorate
+		SubscriptionStatus::Active if subscription.period_start == 0 => (0, 0, new_interval.align(subscription.next_payment_due), 0),
+		SubscriptionStatus::Active => {
+			// The current period must not be over yet
+			if current_time >= subscription.next_payment_due {
+				return err!(QBitFlowError::PlanChangeNotAllowed);
+			}
+
+			// The current period ends on the first anchor date of the new interval at or after the current due date
+			let new_payment_due = new_interval.align(subscription.next_payment_due);
+			let new_period_start = new_interval.previous_due(new_payment_due)?;
+
+			(
+				prorate(new_plan.price, new_period_start, new_payment_due, current_time),
+				prorate(subscription.last_payment_amount, subscription.period_start, subscription.next_payment_due, current_time),
+				new_payment_due,
+				new_period_start,
+			)
+		}
+		_ => return err!(QBitFlowError::PlanChangeNotAllowed),
+	};
 
+	// Settle the prorated charge with the credits (left by previous downgrades, and for the unused part of the current period)
+	let (amount_charged, credit) = apply_credit(subscription.credit, prorated_charge, prorated_credit)?;
+	subscription.credit = credit;
 
-/**
- * Force cancel a subscription
- * Can only be called by the authority owner
- * Unlike regular cancel, this doesn't need the signature of the subscriber, and does not perform time checks
- * This is useful for admin purposes, or if the subscriber has lost access to their account
- */
-pub fn force_cancel_subscription(
-	ctx: Context<ForceCancelSubscription>,
-	_uuid: [u8; 16]
-) -> Result<()> {
-	let subscription = &ctx.accounts.subscription;
+	let settlement = Settlement::compute(&ctx.accounts.mint.to_account_info(), amount_charged, fee_bps, organization_fee_bps, transfer_fee_policy)?;
+	let total_debited = settlement.total_debited()?;
 
-	// Revoke the allowance from the permit registry
 	let permit_registry = &mut ctx.accounts.permit_registry;
-	permit_registry.revoke_allowance(subscription)?;
 
-	// Close the subscription account by setting the close constraint to the subscriber
-	ctx.accounts.subscription.close(ctx.accounts.authority_and_owner.owner.to_account_info())?;
+	if total_debited > 0 {
+		// Ensure the subscription and the global permit registry have enough allowance left
+		if subscription.used_allowance + total_debited >= subscription.allowance || !permit_registry.has_enough_allowance(total_debited) {
+			return err!(QBitFlowError::InsufficientAllowance);
+		}
 
-	if permit_registry.total_allowance == 0 {
-		// If the permit registry has no more allowance, close it as well
-		permit_registry.close(ctx.accounts.authority_and_owner.owner.to_account_info())?;
+		settlement.transfer(
+			&ctx.accounts.token_program,
+			&ctx.accounts.subscriber_token_account,
+			&ctx.accounts.mint,
+			&ctx.accounts.fee_recipient_token_account,
+			&ctx.accounts.organization_token_account,
+			&ctx.accounts.merchant_token_account,
+			&ctx.accounts.authority_and_owner.authority,
+			ctx.remaining_accounts,
+		)?;
 	}
 
-	emit!(SubscriptionCancelled {
-		uuid: _uuid,
+	// Compute refund in tokens for the authority owner to refund the compute cost paid in SOL
+	let refund_result = compute_refund(uuid, new_max_amount - new_plan.price, compute_refund_params, ctx.accounts.mint.decimals, CpiContext::new(
+		ctx.accounts.token_program.to_account_info(),
+		TransferChecked {
+			from: ctx.accounts.subscriber_token_account.to_account_info(),
+			mint: ctx.accounts.mint.to_account_info(),
+			to: ctx.accounts.fee_recipient_token_account.to_account_info(),
+			authority: ctx.accounts.subscriber.to_account_info(),
+		},
+	).with_remaining_accounts(ctx.remaining_accounts.to_vec()))?;
+
+	use_allowance(subscription, permit_registry, total_debited + refund_result)?;
+
+	// Switch to the new plan (the rest of the current period is now paid at the new price)
+	// The plan sets the price, so a price change proposed on the standalone subscription no longer applies
+	subscription.plan = new_plan.key();
+	subscription.pending_price_change = None;
+	subscription.max_amount = new_max_amount;
+	if subscription.status == SubscriptionStatus::Active {
+		subscription.last_payment_amount = new_plan.price;
+	}
+
+	// The schedule follows the new billing interval from now on
+	subscription.billing_interval = new_interval;
+	subscription.next_payment_due = next_payment_due;
+	subscription.period_start = period_start;
+	subscription.subscription_hash = create_subscription_hash(
+		&ctx.accounts.merchant_token_account.key(),
+		&ctx.accounts.subscriber_token_account.key(),
+		&subscription.billing_interval,
+		&ctx.accounts.organization_token_account.key()
+	);
+
+	emit!(SubscriptionPlanChanged {
+		uuid,
+		plan: new_plan.key(),
+		price: new_plan.price,
+		prorated_charge,
+		prorated_credit,
+		amount_charged,
+		credit: subscription.credit,
 	});
 
-    // The account will be closed automatically due to the close constraint
-    Ok(())
+	Ok(())
 }
 
 
+// Part of `amount` (paid for the period from `period_start` to `period_end`) covering the time left at `now`
+// The result exceeds `amount` if `now` is before `period_start` (more than a period left)
+fn prorate(amount: u64, period_start: i64, period_end: i64, now: i64) -> u64 {
+	let remaining = (period_end - now) as u128;
+	let period_length = (period_end - period_start) as u128;
+
+	(amount as u128 * remaining / period_length) as u64
+}
+
+// Settle a prorated charge with the credit balance (the subscription credit and the prorated credit)
+// Returns the amount left to charge, and the credit left for the next payments
+fn apply_credit(credit: u64, prorated_charge: u64, prorated_credit: u64) -> Result<(u64, u64)> {
+	let credit_balance = credit.checked_add(prorated_credit).ok_or(QBitFlowError::Overflow)?;
+	Ok((prorated_charge.saturating_sub(credit_balance), credit_balance.saturating_sub(prorated_charge)))
+}
 
 
 /**
@@ -681,8 +1934,8 @@ pub fn force_cancel_subscription(
  * It also resets the used allowance to 0
  * The permit registry is updated accordingly
  */
-pub fn increase_allowance(
-    ctx: Context<IncreaseAllowance>,
+pub fn increase_allowance<'info>(
+    ctx: Context<'_, '_, '_, 'info, IncreaseAllowance<'info>>,
     uuid: [u8; 16],
     new_allowance: u64,
 	compute_refund_params: ComputeRefundData,
@@ -693,6 +1946,11 @@ pub fn increase_allowance(
 
     let subscription = &mut ctx.accounts.subscription;
 
+	// The allowance of a canceled regular subscription has already been released
+	if subscription.status == SubscriptionStatus::Ended || (subscription.status == SubscriptionStatus::PendingCancel && !subscription.is_payg) {
+		return err!(QBitFlowError::SubscriptionEnded);
+	}
+
 	// Allowance can only be increased
 	if new_allowance <= subscription.allowance {
 		return err!(QBitFlowError::InvalidAmount);
@@ -709,25 +1967,23 @@ pub fn increase_allowance(
 	subscription.used_allowance = 0; // Reset used allowance
 
 	// And add the new allowance to the permit registry
-	permit_registry.add_allowance(new_allowance, &ctx.accounts.subscriber, &ctx.accounts.authority_and_owner.authority, &ctx.accounts.token_program, &ctx.accounts.subscriber_token_account)?;
+	permit_registry.add_allowance(new_allowance, &ctx.accounts.subscriber, &ctx.accounts.authority_and_owner.authority, &ctx.accounts.token_program, &ctx.accounts.subscriber_token_account, &ctx.accounts.mint)?;
 
 
 	// Compute refund in tokens for the authority owner to refund the compute cost paid in SOL
-	let refund_result = compute_refund(uuid, subscription.max_amount - subscription.last_payment_amount, compute_refund_params, CpiContext::new(
+	let refund_result = compute_refund(uuid, subscription.max_amount - subscription.last_payment_amount, compute_refund_params, ctx.accounts.mint.decimals, CpiContext::new(
 		ctx.accounts.token_program.to_account_info(),
-		Transfer {
+		TransferChecked {
 			from: ctx.accounts.subscriber_token_account.to_account_info(),
+			mint: ctx.accounts.mint.to_account_info(),
 			to: ctx.accounts.fee_recipient_token_account.to_account_info(),
 			authority: ctx.accounts.subscriber.to_account_info(),
 		},
-	));
+	).with_remaining_accounts(ctx.remaining_accounts.to_vec()));
 
 	// For increase allowance, we do not enforce the refund to succeed
 	// If it fails, the authority owner pays the compute cost in SOL
-	let refund_result = match refund_result {
-		Ok(amount) => amount,
-		Err(_) => 0,
-	};
+	let refund_result = refund_result.unwrap_or_default();
 
 	// Update the used allowance with the refunded amount (best-effort, if it fails, the authority owner pays the compute cost in SOL)
 	subscription.used_allowance = refund_result; // Update the used allowance for the subscription
@@ -742,9 +1998,186 @@ pub fn increase_allowance(
 }
 
 
-pub fn update_max_amount(ctx: Context<UpdateMaxAmount>, uuid: [u8; 16], new_max_amount: u64, compute_refund_params: ComputeRefundData) -> Result<()> {
+/**
+ * Transfer a subscription to a new wallet of the subscriber (key rotation)
+ * This requires the signatures of both the current and the new subscriber
+ * The subscription keeps its uuid, terms and history, and the next payments are made from the new subscriber's token account
+ * The allowance share of the subscription (allowance and used allowance) is moved from the current subscriber's permit registry to the new subscriber's one,
+ * and the program's delegate PDA is approved again on both token accounts with their new effective allowances
+ * The current subscriber's permit registry is closed if it has no more allowance
+ * Emits a SubscriptionTransferred event
+ */
+pub fn transfer_subscription(
+	ctx: Context<TransferSubscription>,
+	uuid: [u8; 16],
+) -> Result<()> {
+	let subscription = &mut ctx.accounts.subscription;
+
+	if subscription.status == SubscriptionStatus::Ended {
+		return err!(QBitFlowError::SubscriptionEnded);
+	}
+
+	let previous_subscriber = ctx.accounts.subscriber.key();
+	let subscriber = ctx.accounts.new_subscriber.key();
+	if subscriber == previous_subscriber {
+		return err!(QBitFlowError::InvalidSubscriptionParameters);
+	}
+
+	// Move the allowance share of the subscription to the new subscriber's permit registry
+	let permit_registry = &mut ctx.accounts.permit_registry;
+	permit_registry.revoke_allowance(subscription)?;
+
+	let new_permit_registry = &mut ctx.accounts.new_permit_registry;
+	if new_permit_registry.bump == 0 {
+		// Newly initialized, set initial values
+		new_permit_registry.bump = ctx.bumps.new_permit_registry;
+	}
+	new_permit_registry.restore_allowance(subscription)?;
+
+	// Approve the new effective allowances with the token program
+	new_permit_registry.set_permit(&ctx.accounts.token_program, &ctx.accounts.new_subscriber, &ctx.accounts.authority_and_owner.authority, &ctx.accounts.new_subscriber_token_account, &ctx.accounts.mint)?;
+	permit_registry.set_permit(&ctx.accounts.token_program, &ctx.accounts.subscriber, &ctx.accounts.authority_and_owner.authority, &ctx.accounts.subscriber_token_account, &ctx.accounts.mint)?;
+
+	// Re-home the subscription, its hash now commits to the new subscriber's token account
+	subscription.subscriber = subscriber;
+	subscription.subscription_hash = create_subscription_hash(
+		&subscription.merchant_token_account,
+		&ctx.accounts.new_subscriber_token_account.key(),
+		&subscription.billing_interval,
+		&subscription.organization_token_account
+	);
+
+	if permit_registry.total_allowance == 0 {
+		// If the permit registry has no more allowance, close it
+		permit_registry.close(ctx.accounts.authority_and_owner.owner.to_account_info())?;
+	}
+
+	emit!(SubscriptionTransferred {
+		uuid,
+		previous_subscriber,
+		subscriber,
+	});
+
+	Ok(())
+}
+
+
+/**
+ * Revoke everything for a mint (kill switch, e.g. if the subscriber's wallet is compromised)
+ * This can be called by the subscriber, and requires their signature
+ * Every subscription of the subscriber in this mint must be passed in the remaining accounts (writable), with their uuids in the same order
+ * The subscriptions are canceled immediately (without time checks, and pending pay-as-you-go usage is not charged), the delegate of the subscriber's token account is revoked,
+ * and the subscriptions and the permit registry are closed (the rent is returned to the authority owner, who paid for it)
+ * Emits a SubscriptionCancelled event per subscription, and an AllAllowancesRevoked event
+ */
+pub fn revoke_all<'info>(
+	ctx: Context<'_, '_, 'info, 'info, RevokeAll<'info>>,
+	uuids: Vec<[u8; 16]>,
+) -> Result<()> {
+	if uuids.len() != ctx.remaining_accounts.len() {
+		return err!(QBitFlowError::InvalidSubscriptionParameters);
+	}
+
+	let permit_registry = &mut ctx.accounts.permit_registry;
+	let owner = ctx.accounts.authority_and_owner.owner.to_account_info();
+
+	for (uuid, subscription_info) in uuids.iter().zip(ctx.remaining_accounts.iter()) {
+		// Deserializing checks the account is owned by the program (a subscription closed earlier in the loop fails here)
+		let mut subscription = Account::<Subscription>::try_from(subscription_info)?;
+
+		// The account must be the subscription PDA of the uuid, of this subscriber and mint
+		let expected = Pubkey::create_program_address(&[SUBSCRIPTION_PDA_SEED, uuid.as_ref(), &[subscription.bump]], &crate::ID)
+			.map_err(|_| QBitFlowError::InvalidSubscriptionParameters)?;
+		if subscription_info.key() != expected
+			|| subscription.subscriber != ctx.accounts.subscriber.key()
+			|| subscription.mint != ctx.accounts.mint.key() {
+			return err!(QBitFlowError::InvalidSubscriptionParameters);
+		}
+
+		subscription.set_status(*uuid, SubscriptionStatus::Ended)?;
+		permit_registry.revoke_allowance(&subscription)?;
+
+		subscription.close(owner.clone())?;
+
+		emit!(SubscriptionCancelled {
+			uuid: *uuid,
+			reason: CancellationReason::Subscriber,
+		});
+	}
+
+	// All the subscriptions holding an allowance must have been provided, so none of them is left without its permit registry
+	// (subscriptions pending cancellation released theirs, and are closed by close_canceled_subscription)
+	if permit_registry.total_allowance != 0 {
+		return err!(QBitFlowError::SubscriptionsRemaining);
+	}
+
+	// Revoke the delegate of the subscriber's token account
+	token_interface::revoke(CpiContext::new(
+		ctx.accounts.token_program.to_account_info(),
+		Revoke {
+			source: ctx.accounts.subscriber_token_account.to_account_info(),
+			authority: ctx.accounts.subscriber.to_account_info(),
+		},
+	))?;
+
+	permit_registry.close(owner)?;
+
+	emit!(AllAllowancesRevoked {
+		subscriber: ctx.accounts.subscriber.key(),
+		mint: ctx.accounts.mint.key(),
+		subscriptions_closed: uuids.len() as u32,
+	});
+
+	Ok(())
+}
+
+
+/**
+ * Decrease the allowance of a subscription
+ * This can be called by the subscriber, and requires their signature
+ * Unlike increase_allowance, the used allowance is kept: the new allowance can never be below what has already been used
+ * The permit registry totals are updated, and the program's delegate PDA is approved again with the smaller effective allowance
+ */
+pub fn decrease_allowance(
+	ctx: Context<DecreaseAllowance>,
+	uuid: [u8; 16],
+	new_allowance: u64,
+) -> Result<()> {
+	let subscription = &mut ctx.accounts.subscription;
+
+	if subscription.status == SubscriptionStatus::Ended {
+		return err!(QBitFlowError::SubscriptionEnded);
+	}
+
+	// Allowance can only be decreased, and never below the used allowance
+	if new_allowance >= subscription.allowance || new_allowance < subscription.used_allowance {
+		return err!(QBitFlowError::InvalidAmount);
+	}
+
+	let permit_registry = &mut ctx.accounts.permit_registry;
+	permit_registry.reduce_allowance(subscription.allowance - new_allowance)?;
+
+	subscription.allowance = new_allowance;
+
+	// Approve the smaller effective allowance with the token program
+	permit_registry.set_permit(&ctx.accounts.token_program, &ctx.accounts.subscriber, &ctx.accounts.authority_and_owner.authority, &ctx.accounts.subscriber_token_account, &ctx.accounts.mint)?;
+
+	emit!(AllowanceDecreased {
+		new_allowance,
+		uuid,
+	});
+
+	Ok(())
+}
+
+
+pub fn update_max_amount<'info>(ctx: Context<'_, '_, '_, 'info, UpdateMaxAmount<'info>>, uuid: [u8; 16], new_max_amount: u64, compute_refund_params: ComputeRefundData) -> Result<()> {
 	let subscription = &mut ctx.accounts.subscription;
 
+	if subscription.status == SubscriptionStatus::Ended {
+		return err!(QBitFlowError::SubscriptionEnded);
+	}
+
 	if new_max_amount == 0 {
 		return err!(QBitFlowError::ZeroAmount);
 	}
@@ -757,14 +2190,15 @@ pub fn update_max_amount(ctx: Context<UpdateMaxAmount>, uuid: [u8; 16], new_max_
 
 
 	// Refund the compute cost to the authority owner
-	let refund_result = compute_refund(uuid, new_max_amount - subscription.last_payment_amount, compute_refund_params, CpiContext::new(
+	let refund_result = compute_refund(uuid, new_max_amount - subscription.last_payment_amount, compute_refund_params, ctx.accounts.mint.decimals, CpiContext::new(
 		ctx.accounts.token_program.to_account_info(),
-		Transfer {
+		TransferChecked {
 			from: ctx.accounts.subscriber_token_account.to_account_info(),
+			mint: ctx.accounts.mint.to_account_info(),
 			to: ctx.accounts.fee_recipient_token_account.to_account_info(),
 			authority: ctx.accounts.subscriber.to_account_info(),
 		},
-	))?;
+	).with_remaining_accounts(ctx.remaining_accounts.to_vec()))?;
 
 	let permit_registry = &mut ctx.accounts.permit_registry;
 
@@ -777,4 +2211,266 @@ pub fn update_max_amount(ctx: Context<UpdateMaxAmount>, uuid: [u8; 16], new_max_
 	});
 
 	Ok(())
-}
\ No newline at end of file
+}
+
+
+/**
+ * Propose a new price for a standalone subscription
+ * This can be called by the merchant of the subscription, and requires their signature
+ * The new price applies from effective_at, which must be at least one billing period after the next billing date (notice period)
+ * The subscriber must accept it with accept_price_change before effective_at, otherwise the subscription is canceled on its first execution from effective_at
+ * A new proposal replaces the pending one
+ * Emits a PriceChangeProposed event
+ */
+pub fn propose_price_change(
+	ctx: Context<ProposePriceChange>,
+	uuid: [u8; 16],
+	price: u64,
+	effective_at: i64,
+) -> Result<()> {
+	let subscription = &mut ctx.accounts.subscription;
+
+	if price == 0 {
+		return err!(QBitFlowError::ZeroAmount);
+	}
+
+	// Pay-as-you-go subscriptions are billed for their usage, and plan subscriptions are billed the plan price
+	if subscription.is_payg || subscription.plan != Pubkey::default() {
+		return err!(QBitFlowError::InvalidSubscriptionParameters);
+	}
+
+	match subscription.status {
+		SubscriptionStatus::PendingCancel | SubscriptionStatus::Ended => return err!(QBitFlowError::SubscriptionEnded),
+		_ => {}
+	}
+
+	// The subscriber must be notified at least one billing period in advance (a full period billed at the current price after the next billing date)
+	if effective_at < subscription.billing_interval.notice_end(subscription.next_payment_due, Clock::get()?.unix_timestamp)? {
+		return err!(QBitFlowError::InvalidEffectiveDate);
+	}
+
+	subscription.pending_price_change = Some(PriceChange {
+		price,
+		effective_at,
+		accepted_max_amount: 0,
+	});
+
+	emit!(PriceChangeProposed {
+		uuid,
+		price,
+		effective_at,
+	});
+
+	Ok(())
+}
+
+
+/**
+ * Accept the price change proposed by the merchant
+ * This can be called by the subscriber, and requires their signature
+ * The new max amount (it must cover the new price) is stored with the price change, and only applied from effective_at, so the new price cannot be charged during the notice period
+ * The allowance can be increased at the same time, immediately (new_allowance equal to the current allowance keeps it)
+ * Accepting again before effective_at replaces the accepted max amount
+ * Emits a PriceChangeAccepted event
+ */
+pub fn accept_price_change<'info>(
+	ctx: Context<'_, '_, '_, 'info, AcceptPriceChange<'info>>,
+	uuid: [u8; 16],
+	new_max_amount: u64,
+	new_allowance: u64,
+	compute_refund_params: ComputeRefundData,
+) -> Result<()> {
+	let subscription = &mut ctx.accounts.subscription;
+
+	// Price changes cannot be proposed to canceled subscriptions, but the subscription may have been canceled after the proposal
+	if subscription.status == SubscriptionStatus::PendingCancel {
+		return err!(QBitFlowError::SubscriptionEnded);
+	}
+
+	let Some(price_change) = subscription.pending_price_change else {
+		return err!(QBitFlowError::NoPendingPriceChange);
+	};
+
+	if Clock::get()?.unix_timestamp >= price_change.effective_at {
+		return err!(QBitFlowError::PriceChangeExpired);
+	}
+
+	if new_max_amount <= price_change.price {
+		return err!(QBitFlowError::MaxAmountInvalid);
+	}
+
+	// Allowance can only be increased
+	if new_allowance < subscription.allowance {
+		return err!(QBitFlowError::InvalidAmount);
+	}
+
+	let permit_registry = &mut ctx.accounts.permit_registry;
+
+	if new_allowance > subscription.allowance {
+		// Replace the allowance (like increase_allowance)
+		permit_registry.revoke_allowance(subscription)?;
+
+		subscription.allowance = new_allowance;
+		subscription.used_allowance = 0;
+
+		permit_registry.add_allowance(new_allowance, &ctx.accounts.subscriber, &ctx.accounts.authority_and_owner.authority, &ctx.accounts.token_program, &ctx.accounts.subscriber_token_account, &ctx.accounts.mint)?;
+	}
+
+	// Keep the price change pending until its effective date, with the accepted max amount
+	subscription.pending_price_change = Some(PriceChange {
+		accepted_max_amount: new_max_amount,
+		..price_change
+	});
+
+	// Refund the compute cost to the authority owner
+	let refund_result = compute_refund(uuid, new_max_amount - price_change.price, compute_refund_params, ctx.accounts.mint.decimals, CpiContext::new(
+		ctx.accounts.token_program.to_account_info(),
+		TransferChecked {
+			from: ctx.accounts.subscriber_token_account.to_account_info(),
+			mint: ctx.accounts.mint.to_account_info(),
+			to: ctx.accounts.fee_recipient_token_account.to_account_info(),
+			authority: ctx.accounts.subscriber.to_account_info(),
+		},
+	).with_remaining_accounts(ctx.remaining_accounts.to_vec()))?;
+
+	permit_registry.use_allowance(refund_result)?;
+	subscription.used_allowance += refund_result;
+
+	emit!(PriceChangeAccepted {
+		uuid,
+		price: price_change.price,
+		new_max_amount,
+		new_allowance,
+	});
+
+	Ok(())
+}
+
+
+/**
+ * Migrate a legacy subscription account (which only stores the hash of its terms) to the current layout
+ * The terms provided are verified against the subscription hash, then stored in the account
+ * Requires the authority owner signature, and the authority owner pays for the additional rent
+ * is_payg is not part of the hash, and is trusted from the authority owner (as it was on every execution before the migration)
+ */
+pub fn migrate_subscription(
+	ctx: Context<MigrateSubscription>,
+	_uuid: [u8; 16],
+	frequency: u32,
+	is_payg: bool,
+) -> Result<()> {
+	let subscription_info = ctx.accounts.subscription.to_account_info();
+
+	{
+		let data = subscription_info.try_borrow_data()?;
+		if data.len() < Subscription::LEGACY_LEN || data[..8] != *Subscription::DISCRIMINATOR {
+			return err!(anchor_lang::error::ErrorCode::AccountDidNotDeserialize);
+		}
+		if data.len() >= Subscription::LEN {
+			return err!(QBitFlowError::SubscriptionAlreadyMigrated);
+		}
+	}
+
+	// Extend the account to the current layout (the authority owner pays for the additional rent)
+	let missing_rent = Rent::get()?.minimum_balance(Subscription::LEN).saturating_sub(subscription_info.lamports());
+	if missing_rent > 0 {
+		anchor_lang::system_program::transfer(
+			CpiContext::new(
+				ctx.accounts.system_program.to_account_info(),
+				anchor_lang::system_program::Transfer {
+					from: ctx.accounts.authority_and_owner.owner.to_account_info(),
+					to: subscription_info.clone(),
+				},
+			),
+			missing_rent,
+		)?;
+	}
+	subscription_info.resize(Subscription::LEN)?;
+
+	// The new fields are appended to the legacy layout, so the extended account deserializes with zeroed terms
+	let mut subscription = Subscription::try_deserialize(&mut &subscription_info.try_borrow_data()?[..])?;
+
+	// Ensure the terms provided match the ones the subscription was created with
+	let computed_hash = create_subscription_hash(
+		&ctx.accounts.merchant_token_account.key(),
+		&ctx.accounts.subscriber_token_account.key(),
+		&BillingInterval::Seconds(frequency),
+		&ctx.accounts.organization_token_account.key()
+	);
+	if subscription.subscription_hash != computed_hash || ctx.accounts.subscriber_token_account.owner != subscription.subscriber {
+		return err!(QBitFlowError::InvalidSubscriptionParameters);
+	}
+
+	let mint = ctx.accounts.subscriber_token_account.mint;
+	if ctx.accounts.merchant_token_account.mint != mint || ctx.accounts.organization_token_account.mint != mint {
+		return err!(QBitFlowError::InvalidSubscriptionParameters);
+	}
+
+	subscription.version = Subscription::VERSION;
+	subscription.merchant = ctx.accounts.merchant_token_account.owner;
+	subscription.merchant_token_account = ctx.accounts.merchant_token_account.key();
+	subscription.organization_token_account = ctx.accounts.organization_token_account.key();
+	subscription.mint = mint;
+	subscription.billing_interval = BillingInterval::Seconds(frequency);
+	subscription.is_payg = is_payg;
+	subscription.status = if subscription.stopped { SubscriptionStatus::PendingCancel } else { SubscriptionStatus::Active };
+	subscription.status_before_pause = subscription.status;
+
+	let mut data = subscription_info.try_borrow_mut_data()?;
+	subscription.try_serialize(&mut &mut data[..])?;
+
+	Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const DAY: i64 = 86400;
+
+	#[test]
+	fn prorate_the_time_left() {
+		// Half of a 30 days period left
+		assert_eq!(prorate(3000, 0, 30 * DAY, 15 * DAY), 1500);
+		assert_eq!(prorate(3000, 0, 30 * DAY, 0), 3000);
+		assert_eq!(prorate(3000, 0, 30 * DAY, 30 * DAY - 1), 0);
+
+		// Rounded down
+		assert_eq!(prorate(1000, 0, 3 * DAY, 2 * DAY), 333);
+
+		// More than a period left (e.g. from a yearly plan to a monthly plan)
+		assert_eq!(prorate(100, 30 * DAY, 60 * DAY, 0), 200);
+	}
+
+	#[test]
+	fn upgrade_is_charged() {
+		// From 1000 to 3000 per 30 days, with 10 days left: 1000 charged, 333 credited
+		let charge = prorate(3000, 0, 30 * DAY, 20 * DAY);
+		let credit = prorate(1000, 0, 30 * DAY, 20 * DAY);
+		assert_eq!((charge, credit), (1000, 333));
+
+		assert_eq!(apply_credit(0, charge, credit).unwrap(), (667, 0));
+
+		// The credit left by a previous downgrade is used first
+		assert_eq!(apply_credit(500, charge, credit).unwrap(), (167, 0));
+		assert_eq!(apply_credit(1000, charge, credit).unwrap(), (0, 333));
+	}
+
+	#[test]
+	fn downgrade_leaves_a_credit() {
+		// From 3000 to 1000 per 30 days, with 15 days left: 500 charged, 1500 credited
+		let charge = prorate(1000, 0, 30 * DAY, 15 * DAY);
+		let credit = prorate(3000, 0, 30 * DAY, 15 * DAY);
+
+		assert_eq!(apply_credit(0, charge, credit).unwrap(), (0, 1000));
+		assert_eq!(apply_credit(200, charge, credit).unwrap(), (0, 1200));
+	}
+
+	#[test]
+	fn nothing_prorated() {
+		assert_eq!(apply_credit(0, 0, 0).unwrap(), (0, 0));
+		assert_eq!(apply_credit(250, 0, 0).unwrap(), (0, 250));
+		assert!(apply_credit(u64::MAX, 0, 1).is_err());
+	}
+}
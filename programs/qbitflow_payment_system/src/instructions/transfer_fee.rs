@@ -0,0 +1,120 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_2022::spl_token_2022::{
+	self,
+	extension::{transfer_fee::{TransferFee, TransferFeeConfig}, BaseStateWithExtensions, StateWithExtensions},
+};
+
+use crate::errors::*;
+
+
+// Who pays the Token-2022 transfer fee (if the mint has the transfer-fee extension)
+// - PayerCovers: every transfer is grossed up, so each recipient receives exactly its share of `amount` (the payer is debited more than `amount`)
+// - RecipientsAbsorb: every transfer sends exactly its share of `amount`, and the transfer fee is withheld from what each recipient receives
+// For mints without the extension (including every legacy SPL Token mint), both policies behave the same
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum TransferFeePolicy {
+	PayerCovers,
+	RecipientsAbsorb,
+}
+
+
+// Amounts of a single token transfer
+// gross: the amount debited from the source account
+// net: the amount actually credited to the destination account (gross - transfer fee)
+#[derive(Clone, Copy, Default)]
+pub struct TransferAmounts {
+	pub gross: u64,
+	pub net: u64,
+}
+
+
+impl TransferAmounts {
+	// Compute the gross and net amounts of a transfer of `amount` (the share intended for the recipient), according to the policy
+	#[inline(never)]
+	pub fn compute(mint: &AccountInfo, amount: u64, policy: TransferFeePolicy) -> Result<Self> {
+		let config = match get_transfer_fee_config(mint)? {
+			Some(config) => config,
+			None => return Ok(TransferAmounts { gross: amount, net: amount }),
+		};
+
+		TransferAmounts::with_fee(config.get_epoch_fee(Clock::get()?.epoch), amount, policy)
+	}
+
+	// Compute the gross and net amounts of a transfer of `amount` with the given transfer fee, according to the policy
+	pub fn with_fee(transfer_fee: &TransferFee, amount: u64, policy: TransferFeePolicy) -> Result<Self> {
+		match policy {
+			TransferFeePolicy::PayerCovers => {
+				let gross = transfer_fee.calculate_pre_fee_amount(amount).ok_or(QBitFlowError::Overflow)?;
+				Ok(TransferAmounts { gross, net: amount })
+			}
+			TransferFeePolicy::RecipientsAbsorb => {
+				let net = transfer_fee.calculate_post_fee_amount(amount).ok_or(QBitFlowError::Overflow)?;
+				Ok(TransferAmounts { gross: amount, net })
+			}
+		}
+	}
+}
+
+
+// Read the transfer-fee config of a mint
+// Returns None for legacy SPL Token mints, and for Token-2022 mints without the transfer-fee extension
+pub fn get_transfer_fee_config(mint: &AccountInfo) -> Result<Option<TransferFeeConfig>> {
+	if *mint.owner != spl_token_2022::ID {
+		return Ok(None);
+	}
+
+	let mint_data = mint.try_borrow_data()?;
+	let mint_with_extensions = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+
+	Ok(mint_with_extensions.get_extension::<TransferFeeConfig>().ok().copied())
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// 1% transfer fee, capped at 50 tokens
+	fn transfer_fee() -> TransferFee {
+		TransferFee {
+			epoch: 0.into(),
+			maximum_fee: 50.into(),
+			transfer_fee_basis_points: 100.into(),
+		}
+	}
+
+	fn amounts(amount: u64, policy: TransferFeePolicy) -> (u64, u64) {
+		let amounts = TransferAmounts::with_fee(&transfer_fee(), amount, policy).unwrap();
+		(amounts.gross, amounts.net)
+	}
+
+	#[test]
+	fn payer_covers_the_fee() {
+		// The recipient receives exactly the amount, the payer is debited the fee on top
+		assert_eq!(amounts(1000, TransferFeePolicy::PayerCovers), (1011, 1000));
+		assert_eq!(amounts(1_000_000, TransferFeePolicy::PayerCovers), (1_000_050, 1_000_000));
+		assert_eq!(amounts(0, TransferFeePolicy::PayerCovers), (0, 0));
+	}
+
+	#[test]
+	fn recipients_absorb_the_fee() {
+		// The payer is debited exactly the amount, the fee is withheld from what the recipient receives
+		assert_eq!(amounts(1000, TransferFeePolicy::RecipientsAbsorb), (1000, 990));
+		assert_eq!(amounts(1_000_000, TransferFeePolicy::RecipientsAbsorb), (1_000_000, 999_950));
+		assert_eq!(amounts(0, TransferFeePolicy::RecipientsAbsorb), (0, 0));
+	}
+
+	#[test]
+	fn mint_without_transfer_fee() {
+		let key = Pubkey::new_unique();
+		let owner = anchor_spl::token::ID;
+		let mut lamports = 0;
+		let mut data = [];
+		let mint = AccountInfo::new(&key, false, false, &mut lamports, &mut data, &owner, false, 0);
+
+		for policy in [TransferFeePolicy::PayerCovers, TransferFeePolicy::RecipientsAbsorb] {
+			let amounts = TransferAmounts::compute(&mint, 1000, policy).unwrap();
+			assert_eq!((amounts.gross, amounts.net), (1000, 1000));
+		}
+	}
+}
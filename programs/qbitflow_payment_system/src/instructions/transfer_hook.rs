@@ -0,0 +1,27 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_2022::spl_token_2022;
+use anchor_spl::token_interface::TransferChecked;
+
+
+// Drop-in replacement for token_interface::transfer_checked, for mints that may have a transfer hook (Token-2022)
+// The extra accounts required by the hook (the hook program, its validation account, and the accounts it resolves) must be passed in the CPI context's remaining accounts
+// The instructions simply forward their own remaining_accounts: the helper only picks the accounts the hook actually needs
+// For legacy SPL Token mints, and for Token-2022 mints without a transfer hook, the remaining accounts are ignored and this behaves like transfer_checked
+#[inline(never)]
+pub fn transfer_checked_with_hook<'info>(
+	ctx: CpiContext<'_, '_, '_, 'info, TransferChecked<'info>>,
+	amount: u64,
+	decimals: u8,
+) -> Result<()> {
+	spl_token_2022::onchain::invoke_transfer_checked(
+		ctx.program.key,
+		ctx.accounts.from,
+		ctx.accounts.mint,
+		ctx.accounts.to,
+		ctx.accounts.authority,
+		&ctx.remaining_accounts,
+		amount,
+		decimals,
+		ctx.signer_seeds,
+	).map_err(Into::into)
+}
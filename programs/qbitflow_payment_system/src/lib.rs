@@ -16,9 +16,17 @@ pub mod state;
 
 use instructions::*;
 use crate::instructions::compute_refund::ComputeRefundData;
+use crate::instructions::transfer_fee::TransferFeePolicy;
+use crate::state::{BillingInterval, CatchUpPolicy, DunningConfig};
 
 
 
+// The code generated by #[program] (IDL instructions) still calls the deprecated AccountInfo::realloc
+// It is emitted next to the program module, so the program is declared in its own module to keep the allow scoped to it
+#[allow(deprecated)]
+mod program_module {
+use super::*;
+
 #[program]
 pub mod qbitflow_payment_system {
 
@@ -36,6 +44,12 @@ pub mod qbitflow_payment_system {
 		instructions::update_owner(ctx, new_owner)
 	}
 
+	// Set the arbiter settling escrow disputes (owner and co-signer must sign)
+	// Creates the escrow configuration account on the first call
+	pub fn set_arbiter(ctx: Context<SetArbiter>, new_arbiter: Pubkey) -> Result<()> {
+		instructions::set_arbiter(ctx, new_arbiter)
+	}
+
 
 	/**
 	 * This function sets the delegate allowance on the user's token account to the current effective allowance
@@ -57,59 +71,193 @@ pub mod qbitflow_payment_system {
         instructions::process_sol_payment(ctx, amount, fee_bps, uuid, organization_fee_bps)
     }
 
+	// Token payments take a "TransferFeePolicy" parameter, which decides who pays the Token-2022 transfer fee (payer or recipients) for mints with the transfer-fee extension
+	// The net amounts received by each party are reported in the payment events
+	// For Token-2022 mints with a transfer hook, the extra accounts required by the hook must be passed as remaining accounts (they are forwarded to every token transfer, including the compute refund)
+
 	// Functions with a "ComputeRefundData" parameter are paid in SPL tokens, but the authority owner pays the compute fees in SOL, and is refunded in tokens by the user
 	// This enables gasless transactions for the user, as the authority owner pays the compute fees
 	// The refund is a best-effort basis, if it fails, the authority owner will have to pay the compute fees in SOL without being refunded in tokens. Shouldn't happen often
 
     /// Process a one-time payment in SPL tokens
-    pub fn process_token_payment(
-        ctx: Context<ProcessTokenPayment>,
+    pub fn process_token_payment<'info>(
+        ctx: Context<'_, '_, '_, 'info, ProcessTokenPayment<'info>>,
         amount: u64,
         fee_bps: u16,
         uuid: [u8; 16],
         organization_fee_bps: u16,
 		compute_refund_params: ComputeRefundData,
+		transfer_fee_policy: TransferFeePolicy,
     ) -> Result<()> {
-        instructions::process_token_payment(ctx, amount, fee_bps, uuid, organization_fee_bps, compute_refund_params)
+        instructions::process_token_payment(ctx, amount, fee_bps, uuid, organization_fee_bps, compute_refund_params, transfer_fee_policy)
     }
 
+	/// Process a one-time payment in SPL tokens, split between several recipients
+	/// The recipients' token accounts are passed as the first remaining accounts, in the order of the shares
+	#[allow(clippy::too_many_arguments)] // Mirrors the client-facing instruction arguments
+	pub fn process_split_payment<'info>(
+		ctx: Context<'_, '_, '_, 'info, ProcessSplitPayment<'info>>,
+		amount: u64,
+		fee_bps: u16,
+		uuid: [u8; 16],
+		organization_fee_bps: u16,
+		shares: Vec<SplitShare>,
+		compute_refund_params: ComputeRefundData,
+		transfer_fee_policy: TransferFeePolicy,
+	) -> Result<()> {
+		instructions::process_split_payment(ctx, amount, fee_bps, uuid, organization_fee_bps, shares, compute_refund_params, transfer_fee_policy)
+	}
+
+	/// Refund a one-time payment (fully or partially), signed by the merchant
+	/// Requires the payment receipt, the cumulative refunded amount can never exceed the amount paid
+	pub fn refund_payment<'info>(
+		ctx: Context<'_, '_, '_, 'info, RefundPayment<'info>>,
+		uuid: [u8; 16],
+		amount: u64,
+	) -> Result<()> {
+		instructions::refund_payment(ctx, uuid, amount)
+	}
+
+	/// Authorize (hold) a token payment: the funds are locked in a vault until captured or voided
+	pub fn authorize_payment<'info>(
+		ctx: Context<'_, '_, '_, 'info, AuthorizePayment<'info>>,
+		uuid: [u8; 16],
+		amount: u64,
+		expires_at: i64,
+		compute_refund_params: ComputeRefundData,
+		transfer_fee_policy: TransferFeePolicy,
+	) -> Result<()> {
+		instructions::authorize_payment(ctx, uuid, amount, expires_at, compute_refund_params, transfer_fee_policy)
+	}
+
+	/// Capture an authorized payment (merchant only), up to the held amount. The rest is returned to the payer
+	pub fn capture_payment<'info>(
+		ctx: Context<'_, '_, '_, 'info, CapturePayment<'info>>,
+		uuid: [u8; 16],
+		amount: u64,
+		fee_bps: u16,
+		organization_fee_bps: u16,
+	) -> Result<()> {
+		instructions::capture_payment(ctx, uuid, amount, fee_bps, organization_fee_bps)
+	}
+
+	/// Void an authorized payment (merchant, or the authority owner alone after expiration), returning the hold to the payer
+	pub fn void_authorization<'info>(
+		ctx: Context<'_, '_, '_, 'info, VoidAuthorization<'info>>,
+		uuid: [u8; 16],
+	) -> Result<()> {
+		instructions::void_authorization(ctx, uuid)
+	}
+
+	/// Create an escrow payment: the funds are locked in a vault until released to the merchant, or settled by the arbiter if disputed
+	#[allow(clippy::too_many_arguments)] // Mirrors the client-facing instruction arguments
+	pub fn create_escrow_payment<'info>(
+		ctx: Context<'_, '_, '_, 'info, CreateEscrowPayment<'info>>,
+		uuid: [u8; 16],
+		amount: u64,
+		fee_bps: u16,
+		organization_fee_bps: u16,
+		release_at: i64,
+		compute_refund_params: ComputeRefundData,
+		transfer_fee_policy: TransferFeePolicy,
+	) -> Result<()> {
+		instructions::create_escrow_payment(ctx, uuid, amount, fee_bps, organization_fee_bps, release_at, compute_refund_params, transfer_fee_policy)
+	}
+
+	/// Release an escrow payment to the merchant (payer, or the authority owner alone after release_at)
+	pub fn release_escrow<'info>(
+		ctx: Context<'_, '_, '_, 'info, ReleaseEscrow<'info>>,
+		uuid: [u8; 16],
+	) -> Result<()> {
+		instructions::release_escrow(ctx, uuid)
+	}
+
+	/// Raise a dispute on an escrow payment (payer only, before release_at)
+	pub fn dispute_escrow(
+		ctx: Context<DisputeEscrow>,
+		uuid: [u8; 16],
+	) -> Result<()> {
+		instructions::dispute_escrow(ctx, uuid)
+	}
+
+	/// Settle a disputed escrow payment (arbiter only): `payer_amount` goes back to the payer, the rest to the merchant
+	pub fn resolve_escrow_dispute<'info>(
+		ctx: Context<'_, '_, '_, 'info, ResolveEscrowDispute<'info>>,
+		uuid: [u8; 16],
+		payer_amount: u64,
+	) -> Result<()> {
+		instructions::resolve_escrow_dispute(ctx, uuid, payer_amount)
+	}
+
+	/// Create a subscription plan (signed by the merchant)
+	pub fn create_plan(
+		ctx: Context<CreatePlan>,
+		plan_id: [u8; 16],
+		price: u64,
+		billing_interval: BillingInterval,
+		trial_period: u32,
+	) -> Result<()> {
+		instructions::create_plan(ctx, plan_id, price, billing_interval, trial_period)
+	}
+
+	/// Activate or deactivate a plan (signed by the merchant)
+	pub fn set_plan_active(
+		ctx: Context<SetPlanActive>,
+		plan_id: [u8; 16],
+		active: bool,
+	) -> Result<()> {
+		instructions::set_plan_active(ctx, plan_id, active)
+	}
+
     /// Create a subscription
-    pub fn create_subscription(
-        ctx: Context<CreateSubscription>,
+    #[allow(clippy::too_many_arguments)] // Mirrors the client-facing instruction arguments
+    pub fn create_subscription<'info>(
+        ctx: Context<'_, '_, '_, 'info, CreateSubscription<'info>>,
         uuid: [u8; 16],
 		amount: u64,
 		max_amount: u64,
-        frequency: u32,
+        billing_interval: BillingInterval,
         allowance: u64,
 		compute_refund_params: ComputeRefundData,
 		is_payg: bool,
+		trial_period: u32,
+		dunning_config: Option<DunningConfig>,
+		catch_up_policy: CatchUpPolicy,
+		max_payments: u32,
+		ends_at: i64,
     ) -> Result<()> {
-        instructions::create_subscription(ctx, uuid, amount, max_amount, frequency, allowance, compute_refund_params, is_payg)
+        instructions::create_subscription(ctx, uuid, amount, max_amount, billing_interval, allowance, compute_refund_params, is_payg, trial_period, dunning_config, catch_up_policy, max_payments, ends_at)
     }
 
     /// Execute a subscription payment
-    pub fn execute_subscription(
-        ctx: Context<ExecuteSubscription>,
+    pub fn execute_subscription<'info>(
+        ctx: Context<'_, '_, '_, 'info, ExecuteSubscription<'info>>,
         amount: u64,
         fee_bps: u16,
         uuid: [u8; 16],
-		frequency: u32,
         organization_fee_bps: u16,
 		compute_refund_params: ComputeRefundData,
-		is_payg: bool,
+		transfer_fee_policy: TransferFeePolicy,
     ) -> Result<()> {
-        instructions::execute_subscription(ctx, amount, fee_bps, uuid, frequency, organization_fee_bps, compute_refund_params, is_payg)
+        instructions::execute_subscription(ctx, amount, fee_bps, uuid, organization_fee_bps, compute_refund_params, transfer_fee_policy)
     }
 
     /// Cancel a subscription
     pub fn cancel_subscription(
         ctx: Context<CancelSubscription>,
         uuid: [u8; 16],
-		is_payg: bool
     ) -> Result<()> {
-        instructions::cancel_subscription(ctx, uuid, is_payg)
+        instructions::cancel_subscription(ctx, uuid)
     }
 
+	/// Close a canceled subscription at the end of its paid period (the permit registry is optional)
+	pub fn close_canceled_subscription(
+		ctx: Context<CloseCanceledSubscription>,
+		uuid: [u8; 16],
+	) -> Result<()> {
+		instructions::close_canceled_subscription(ctx, uuid)
+	}
+
 	// Force cancel a subscription (admin only)
 	pub fn force_cancel_subscription(
 		ctx: Context<ForceCancelSubscription>,
@@ -118,8 +266,8 @@ pub mod qbitflow_payment_system {
 		instructions::force_cancel_subscription(ctx, uuid)
 	}
 
-	pub fn update_max_amount(
-		ctx: Context<UpdateMaxAmount>,
+	pub fn update_max_amount<'info>(
+		ctx: Context<'_, '_, '_, 'info, UpdateMaxAmount<'info>>,
 		uuid: [u8; 16],
 		new_max_amount: u64,
 		compute_refund_params: ComputeRefundData,
@@ -127,13 +275,122 @@ pub mod qbitflow_payment_system {
 		instructions::update_max_amount(ctx, uuid, new_max_amount, compute_refund_params)
 	}
 
+	/// Pause a subscription (signed by the subscriber or the merchant)
+	pub fn pause_subscription(
+		ctx: Context<PauseSubscription>,
+		uuid: [u8; 16],
+	) -> Result<()> {
+		instructions::pause_subscription(ctx, uuid)
+	}
+
+	/// Resume a paused subscription (signed by the subscriber or the merchant)
+	pub fn resume_subscription(
+		ctx: Context<ResumeSubscription>,
+		uuid: [u8; 16],
+	) -> Result<()> {
+		instructions::resume_subscription(ctx, uuid)
+	}
+
+	/// Migrate a legacy subscription account to the current layout (storing its terms), admin only
+	/// The terms provided are verified against the subscription hash
+	pub fn migrate_subscription(
+		ctx: Context<MigrateSubscription>,
+		uuid: [u8; 16],
+		frequency: u32,
+		is_payg: bool,
+	) -> Result<()> {
+		instructions::migrate_subscription(ctx, uuid, frequency, is_payg)
+	}
+
+	/// Change the plan of a subscription with proration (signed by the subscriber)
+	pub fn change_subscription_plan<'info>(
+		ctx: Context<'_, '_, '_, 'info, ChangeSubscriptionPlan<'info>>,
+		uuid: [u8; 16],
+		new_max_amount: u64,
+		fee_bps: u16,
+		organization_fee_bps: u16,
+		compute_refund_params: ComputeRefundData,
+		transfer_fee_policy: TransferFeePolicy,
+	) -> Result<()> {
+		instructions::change_subscription_plan(ctx, uuid, new_max_amount, fee_bps, organization_fee_bps, compute_refund_params, transfer_fee_policy)
+	}
+
+	/// Propose a new price for a standalone subscription (signed by the merchant)
+	pub fn propose_price_change(
+		ctx: Context<ProposePriceChange>,
+		uuid: [u8; 16],
+		price: u64,
+		effective_at: i64,
+	) -> Result<()> {
+		instructions::propose_price_change(ctx, uuid, price, effective_at)
+	}
+
+	/// Accept the price change proposed by the merchant (signed by the subscriber)
+	pub fn accept_price_change<'info>(
+		ctx: Context<'_, '_, '_, 'info, AcceptPriceChange<'info>>,
+		uuid: [u8; 16],
+		new_max_amount: u64,
+		new_allowance: u64,
+		compute_refund_params: ComputeRefundData,
+	) -> Result<()> {
+		instructions::accept_price_change(ctx, uuid, new_max_amount, new_allowance, compute_refund_params)
+	}
+
+	/// Cancel a subscription (signed by the merchant)
+	pub fn merchant_cancel_subscription(
+		ctx: Context<MerchantCancelSubscription>,
+		uuid: [u8; 16],
+	) -> Result<()> {
+		instructions::merchant_cancel_subscription(ctx, uuid)
+	}
+
+	/// Submit the final usage charge of a canceled pay-as-you-go subscription and close it (signed by the merchant)
+	pub fn settle_payg_subscription<'info>(
+		ctx: Context<'_, '_, '_, 'info, SettlePaygSubscription<'info>>,
+		uuid: [u8; 16],
+		amount: u64,
+		fee_bps: u16,
+		organization_fee_bps: u16,
+		compute_refund_params: ComputeRefundData,
+		transfer_fee_policy: TransferFeePolicy,
+	) -> Result<()> {
+		instructions::settle_payg_subscription(ctx, uuid, amount, fee_bps, organization_fee_bps, compute_refund_params, transfer_fee_policy)
+	}
+
     // Increase allowance for a subscription (only subscriber can do this)
-    pub fn increase_allowance(
-        ctx: Context<IncreaseAllowance>,
+    pub fn increase_allowance<'info>(
+        ctx: Context<'_, '_, '_, 'info, IncreaseAllowance<'info>>,
         uuid: [u8; 16],
         new_allowance: u64,
 		compute_refund_params: ComputeRefundData,
     ) -> Result<()> {
         instructions::increase_allowance(ctx, uuid, new_allowance, compute_refund_params)
     }
+
+	// Transfer a subscription to a new wallet (both the current and the new subscriber must sign)
+	pub fn transfer_subscription(
+		ctx: Context<TransferSubscription>,
+		uuid: [u8; 16],
+	) -> Result<()> {
+		instructions::transfer_subscription(ctx, uuid)
+	}
+
+	// Cancel every subscription of the subscriber for a mint and revoke the delegate (only subscriber can do this)
+	pub fn revoke_all<'info>(
+		ctx: Context<'_, '_, 'info, 'info, RevokeAll<'info>>,
+		uuids: Vec<[u8; 16]>,
+	) -> Result<()> {
+		instructions::revoke_all(ctx, uuids)
+	}
+
+	// Decrease allowance for a subscription (only subscriber can do this)
+	pub fn decrease_allowance(
+		ctx: Context<DecreaseAllowance>,
+		uuid: [u8; 16],
+		new_allowance: u64,
+	) -> Result<()> {
+		instructions::decrease_allowance(ctx, uuid, new_allowance)
+	}
+}
 }
+pub use program_module::*;
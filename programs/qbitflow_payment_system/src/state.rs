@@ -1,6 +1,6 @@
 use anchor_lang::prelude::*;
 use anchor_lang::solana_program::hash::{hashv};
-use crate::{AUTHORITY_PDA_SEED, FEE_DENOMINATOR, MAX_FEE_BPS, MIN_FEE_FOR_CONTRACT_BPS};
+use crate::{AUTHORITY_PDA_SEED, FEE_DENOMINATOR, MAX_FEE_BPS, MIN_FEE_FOR_CONTRACT_BPS, MIN_FREQUENCY};
 use crate::errors::*;
 
 
@@ -55,14 +55,74 @@ pub struct Subscription {
 	pub used_allowance: u64, // Cumulative used allowance
 
 	pub subscription_hash: [u8; 32],
-	pub stopped: bool, // Whether the subscription is stopped (for pay-as-you-go)
+	pub stopped: bool, // Legacy flag for stopped pay-as-you-go subscriptions, superseded by status (only read during the migration)
 	pub max_amount: u64, // Maximum amount allowed per period
 	pub last_payment_amount: u64, // Last payment amount
     pub bump: u8,
+
+	// Terms of the subscription (appended in version 1, legacy accounts must be migrated with migrate_subscription)
+	pub version: u8, // Layout version of the account (Subscription::VERSION)
+	pub merchant: Pubkey, // Merchant receiving the payments (main account)
+	pub merchant_token_account: Pubkey, // Token account of the merchant receiving the payments
+	pub organization_token_account: Pubkey, // Token account of the organization receiving a portion of the fees
+	pub mint: Pubkey, // Token mint of the subscription
+	pub billing_interval: BillingInterval, // Billing period
+	pub is_payg: bool, // Whether this is a pay-as-you-go subscription (billed at the end of each period)
+	pub plan: Pubkey, // Plan the subscription is subscribed to (Pubkey::default() for standalone subscriptions)
+	pub trial_ends_at: i64, // End of the free trial (0 if the subscription was created without a trial)
+	pub paused_at: i64, // Timestamp at which the subscription was paused (0 if not paused)
+	pub status: SubscriptionStatus, // Current status of the subscription
+	pub status_before_pause: SubscriptionStatus, // Status restored when a paused subscription is resumed
+
+	// Failed payments handling (dunning)
+	pub dunning_config: Option<DunningConfig>, // None: a failed payment fails the transaction
+	pub failed_attempts: u8, // Consecutive failed payment attempts
+	pub retry_at: i64, // Next payment attempt after a failure (0 if the last attempt succeeded)
+
+	pub catch_up_policy: CatchUpPolicy, // How the periods missed while no payment was executed are charged (regular subscriptions only)
+
+	// Fixed-term subscriptions are completed (and closed) once their last payment is made
+	pub max_payments: u32, // Total number of payments (0 for no limit)
+	pub ends_at: i64, // No payment is due at or after this timestamp (0 for no end)
+	pub payments_made: u32, // Number of payments made so far
+
+	// Plan changes (proration)
+	pub period_start: i64, // Start of the period covered by the last payment (0 if no payment was made yet)
+	pub credit: u64, // Credit left by plan downgrades, deducted from the next payments
+
+	// Price change proposed by the merchant, waiting for the subscriber's consent (None if there is none)
+	pub pending_price_change: Option<PriceChange>,
 }
 
 impl Subscription {
-	pub const LEN: usize = 8 // discriminator
+	pub const VERSION: u8 = 1;
+
+	pub const LEN: usize = Subscription::LEGACY_LEN
+		 + 1   // version
+		 + 32  // merchant
+		 + 32  // merchant_token_account
+		 + 32  // organization_token_account
+		 + 32  // mint
+		 + BillingInterval::LEN // billing_interval
+		 + 1   // is_payg
+		 + 32  // plan
+		 + 8   // trial_ends_at
+		 + 8   // paused_at
+		 + 1   // status
+		 + 1   // status_before_pause
+		 + 1 + DunningConfig::LEN // dunning_config
+		 + 1   // failed_attempts
+		 + 8   // retry_at
+		 + 2   // catch_up_policy
+		 + 4   // max_payments
+		 + 8   // ends_at
+		 + 4   // payments_made
+		 + 8   // period_start
+		 + 8   // credit
+		 + 1 + PriceChange::LEN; // pending_price_change
+
+	// Size of the accounts created before the terms were stored (version 0)
+	pub const LEGACY_LEN: usize = 8 // discriminator
 		 + 32  // subscriber
 		 + 8  // next_payment_due
 		 + 8  // allowance
@@ -72,6 +132,527 @@ impl Subscription {
 		 + 8   // max_amount
 		 + 8   // last_payment_amount
 		 + 1; // bump
+
+	// Move the subscription to a new status, enforcing the valid transitions
+	// Emits a SubscriptionStatusChanged event (nothing happens if the status is unchanged)
+	pub fn set_status(&mut self, uuid: [u8; 16], status: SubscriptionStatus) -> Result<()> {
+		if self.status == status {
+			return Ok(());
+		}
+
+		if !self.status.can_transition_to(status) {
+			return err!(crate::errors::QBitFlowError::InvalidStatusTransition);
+		}
+
+		let previous_status = self.status;
+		self.status = status;
+
+		emit!(SubscriptionStatusChanged {
+			uuid,
+			previous_status,
+			status,
+		});
+
+		Ok(())
+	}
+
+	// Push the schedule of a paused subscription back by the time it was paused (when it is resumed)
+	pub fn shift_schedule(&mut self, paused_duration: i64) -> Result<()> {
+		self.next_payment_due = self.next_payment_due.checked_add(paused_duration).ok_or(crate::errors::QBitFlowError::Overflow)?;
+		if !self.is_payg {
+			// Calendar intervals stay on their anchor: the payment is due on the first anchor date after the shifted due date
+			self.next_payment_due = self.billing_interval.align(self.next_payment_due);
+		}
+		if self.ends_at != 0 && self.next_payment_due >= self.ends_at {
+			if self.is_payg {
+				// The final period of a fixed-term pay-as-you-go subscription is billed at its end date
+				self.next_payment_due = self.ends_at;
+			} else {
+				// A fixed-term regular subscription cannot be resumed past its end date (no payment is due at or after it)
+				return err!(crate::errors::QBitFlowError::ResumePastEndDate);
+			}
+		}
+		if self.retry_at != 0 {
+			self.retry_at = self.retry_at.checked_add(paused_duration).ok_or(crate::errors::QBitFlowError::Overflow)?;
+		}
+		if self.status_before_pause == SubscriptionStatus::Trialing {
+			self.trial_ends_at = self.trial_ends_at.checked_add(paused_duration).ok_or(crate::errors::QBitFlowError::Overflow)?;
+		}
+
+		Ok(())
+	}
+}
+
+
+// Failed payments handling of a subscription
+// A failed payment attempt is recorded (the subscription becomes PastDue) and retried after retry_interval, doubled after each failure
+// The subscription is canceled after max_retries failed retries
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct DunningConfig {
+	pub max_retries: u8,
+	pub retry_interval: u32, // Delay before the first retry, in seconds
+}
+
+impl DunningConfig {
+	pub const LEN: usize = 1 // max_retries
+		+ 4; // retry_interval
+
+	// Whether the subscription must be canceled after its failed_attempts-th consecutive failure
+	pub fn exhausted(&self, failed_attempts: u8) -> bool {
+		failed_attempts > self.max_retries
+	}
+
+	// Delay before retrying after the failed_attempts-th consecutive failure
+	// Exponential backoff: retry_interval, then twice as long after each failure (capped at 2^16 times retry_interval)
+	pub fn retry_delay(&self, failed_attempts: u8) -> i64 {
+		(self.retry_interval as i64).saturating_mul(1i64 << failed_attempts.saturating_sub(1).min(16))
+	}
+}
+
+
+// Reason of a subscription cancellation
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CancellationReason {
+	Subscriber, // Canceled by the subscriber
+	Merchant, // Canceled by the merchant
+	Admin, // Force canceled by the authority owner
+	PaymentFailed, // Too many failed payment attempts
+	PriceChangeNotAccepted, // The subscriber did not accept the price change before its effective date
+}
+
+
+// Price change proposed by the merchant of a standalone subscription
+// The subscriber must accept it before effective_at, otherwise the subscription is canceled on its first execution from effective_at
+// Once accepted, it stays pending until effective_at, when the accepted max amount replaces the current one (on the first execution from effective_at)
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct PriceChange {
+	pub price: u64, // New price per period
+	pub effective_at: i64, // Date from which the new price applies
+	pub accepted_max_amount: u64, // Max amount accepted by the subscriber for the new price (0 if not accepted yet)
+}
+
+impl PriceChange {
+	pub const LEN: usize = 8 // price
+		+ 8 // effective_at
+		+ 8; // accepted_max_amount
+}
+
+
+// Billing period of a subscription
+// - Seconds: fixed number of seconds (at least MIN_FREQUENCY)
+// - Months: calendar months, billed on the anchor day of the month (or the last day of shorter months)
+// - Years: calendar years, billed on the anchor month and day (Feb 29 falls back to Feb 28 on non-leap years, and is billed on Feb 29 again on leap years)
+// Calendar intervals are always billed on their anchor (see align), the first payment included
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BillingInterval {
+	Seconds(u32),
+	Months { count: u8, anchor_day: u8 },
+	Years { count: u8, anchor_month: u8, anchor_day: u8 },
+}
+
+impl BillingInterval {
+	pub const LEN: usize = 1 + 4; // largest variant (Seconds, Years is 3 bytes)
+
+	pub fn validate(&self) -> Result<()> {
+		let valid = match self {
+			BillingInterval::Seconds(seconds) => *seconds >= MIN_FREQUENCY,
+			BillingInterval::Months { count, anchor_day } => *count > 0 && (1..=31).contains(anchor_day),
+			BillingInterval::Years { count, anchor_month, anchor_day } => *count > 0
+				&& (1..=12).contains(anchor_month)
+				&& (1..=days_in_month(2000, *anchor_month as i64)).contains(&(*anchor_day as i64)), // 2000 is a leap year, so Feb 29 is allowed
+		};
+
+		if !valid {
+			return err!(crate::errors::QBitFlowError::InvalidFrequency);
+		}
+
+		Ok(())
+	}
+
+	// First due date on the anchor of the interval at or after `from` (the time of day is kept)
+	// Fixed intervals have no anchor, `from` is returned as is
+	pub fn align(&self, from: i64) -> i64 {
+		let (year, month, day) = civil_from_days(from.div_euclid(86400));
+		let time_of_day = from.rem_euclid(86400);
+
+		let (year, month, day) = match self {
+			BillingInterval::Seconds(_) => return from,
+			BillingInterval::Months { anchor_day, .. } => {
+				let anchor = (*anchor_day as i64).min(days_in_month(year, month));
+				if day <= anchor {
+					(year, month, anchor)
+				} else {
+					// Anchor day of the following month
+					let (year, month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+					(year, month, (*anchor_day as i64).min(days_in_month(year, month)))
+				}
+			}
+			BillingInterval::Years { anchor_month, anchor_day, .. } => {
+				let anchor_month = *anchor_month as i64;
+				let anchor = (anchor_month, (*anchor_day as i64).min(days_in_month(year, anchor_month)));
+				let year = if (month, day) <= anchor { year } else { year + 1 };
+				(year, anchor_month, (*anchor_day as i64).min(days_in_month(year, anchor_month)))
+			}
+		};
+
+		days_from_civil(year, month, day) * 86400 + time_of_day
+	}
+
+	// First payment due date of a subscription created at `now` (trial_ends_at is 0 without a trial)
+	pub fn first_due(&self, is_payg: bool, now: i64, trial_ends_at: i64) -> Result<i64> {
+		if is_payg {
+			// The end of the first period (no trial period for pay-as-you-go), the billing is done at the end of the period
+			self.next_due(now)
+		} else if trial_ends_at != 0 {
+			// The end of the trial (on the next anchor date for calendar intervals), which starts the billing cycle
+			Ok(self.align(trial_ends_at))
+		} else {
+			// Immediately for regular subscriptions (on the next anchor date for calendar intervals, so the first period is a full one)
+			Ok(self.align(now))
+		}
+	}
+
+	// Due date of the period following the one due at `from` (the time of day is kept)
+	pub fn next_due(&self, from: i64) -> Result<i64> {
+		let (year, month, _) = civil_from_days(from.div_euclid(86400));
+		let time_of_day = from.rem_euclid(86400);
+
+		let (year, month, day) = match self {
+			BillingInterval::Seconds(seconds) => {
+				return from.checked_add(*seconds as i64).ok_or(crate::errors::QBitFlowError::Overflow.into());
+			}
+			BillingInterval::Months { count, anchor_day } => {
+				let months = year * 12 + (month - 1) + *count as i64;
+				let (year, month) = (months.div_euclid(12), months.rem_euclid(12) + 1);
+				(year, month, (*anchor_day as i64).min(days_in_month(year, month)))
+			}
+			BillingInterval::Years { count, anchor_month, anchor_day } => {
+				let (year, month) = (year + *count as i64, *anchor_month as i64);
+				(year, month, (*anchor_day as i64).min(days_in_month(year, month)))
+			}
+		};
+
+		Ok(days_from_civil(year, month, day) * 86400 + time_of_day)
+	}
+
+	// Start of the period due at `due`, i.e. the due date of the previous period (the time of day is kept)
+	pub fn previous_due(&self, due: i64) -> Result<i64> {
+		let (year, month, _) = civil_from_days(due.div_euclid(86400));
+		let time_of_day = due.rem_euclid(86400);
+
+		let (year, month, day) = match self {
+			BillingInterval::Seconds(seconds) => {
+				return due.checked_sub(*seconds as i64).ok_or(crate::errors::QBitFlowError::Overflow.into());
+			}
+			BillingInterval::Months { count, anchor_day } => {
+				let months = year * 12 + (month - 1) - *count as i64;
+				let (year, month) = (months.div_euclid(12), months.rem_euclid(12) + 1);
+				(year, month, (*anchor_day as i64).min(days_in_month(year, month)))
+			}
+			BillingInterval::Years { count, anchor_month, anchor_day } => {
+				let (year, month) = (year - *count as i64, *anchor_month as i64);
+				(year, month, (*anchor_day as i64).min(days_in_month(year, month)))
+			}
+		};
+
+		Ok(days_from_civil(year, month, day) * 86400 + time_of_day)
+	}
+
+	// Due date `periods` periods after the one due at `from`
+	pub fn advance(&self, from: i64, periods: u64) -> Result<i64> {
+		if let BillingInterval::Seconds(seconds) = self {
+			return (periods as i64)
+				.checked_mul(*seconds as i64)
+				.and_then(|x| from.checked_add(x))
+				.ok_or(crate::errors::QBitFlowError::Overflow.into());
+		}
+
+		let mut due = from;
+		for _ in 0..periods {
+			due = self.next_due(due)?;
+		}
+		Ok(due)
+	}
+
+	// End of the first full period billed after `now`, i.e. one period after the next billing date (an overdue payment is billed at `now`)
+	// Measured from the billing date rather than from `now`, since calendar intervals snap to their anchor
+	pub fn notice_end(&self, next_payment_due: i64, now: i64) -> Result<i64> {
+		self.next_due(self.align(next_payment_due.max(now)))
+	}
+
+	// Number of periods due by `now`, the first one being due at `first_due` (0 if it is not due yet)
+	pub fn periods_due(&self, first_due: i64, now: i64) -> Result<u64> {
+		if now < first_due {
+			return Ok(0);
+		}
+
+		if let BillingInterval::Seconds(seconds) = self {
+			return Ok((now - first_due) as u64 / *seconds as u64 + 1);
+		}
+
+		let mut periods = 0;
+		let mut due = first_due;
+		while due <= now {
+			periods += 1;
+			due = self.next_due(due)?;
+		}
+		Ok(periods)
+	}
+
+	// Bytes of the interval in the subscription hash (fixed intervals keep the legacy encoding of the frequency)
+	pub fn hash_bytes(&self) -> Vec<u8> {
+		match self {
+			BillingInterval::Seconds(seconds) => seconds.to_le_bytes().to_vec(),
+			BillingInterval::Months { count, anchor_day } => vec![1, *count, *anchor_day],
+			BillingInterval::Years { count, anchor_month, anchor_day } => vec![2, *count, *anchor_month, *anchor_day],
+		}
+	}
+}
+
+
+// How a regular subscription is charged for the periods missed while no payment was executed (e.g. keeper downtime)
+// - ChargeAll: every missed period is charged, one execution per period
+// - SkipToCurrent: only the current period is charged, the missed ones are skipped
+// - MaxPeriods(k): at most k periods (including the current one) are charged, the oldest missed ones are skipped
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CatchUpPolicy {
+	ChargeAll,
+	SkipToCurrent,
+	MaxPeriods(u8),
+}
+
+impl CatchUpPolicy {
+	// Number of missed periods skipped (without being charged), out of the periods owed (the current one included)
+	pub fn periods_to_skip(&self, periods_owed: u64) -> u64 {
+		match self {
+			CatchUpPolicy::ChargeAll => 0,
+			CatchUpPolicy::SkipToCurrent => periods_owed.saturating_sub(1),
+			CatchUpPolicy::MaxPeriods(max_periods) => periods_owed.saturating_sub(*max_periods as u64),
+		}
+	}
+}
+
+
+// Reason of a failed subscription payment
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PaymentFailureReason {
+	InsufficientBalance, // The subscriber's token account does not hold enough tokens
+	DelegateRevoked, // The authority PDA is no longer the delegate of the subscriber's token account
+	InsufficientDelegation, // The delegated amount left is too low
+	AccountFrozen, // The subscriber's token account is frozen
+}
+
+
+// Lifecycle of a subscription
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SubscriptionStatus {
+	Trialing, // In its free trial, nothing has been charged yet
+	Active, // Billed every period
+	PastDue, // The last payment attempt failed
+	Paused, // Billing is frozen until the subscription is resumed
+	PendingCancel, // Canceled, waiting for its final payment
+	Ended, // No more payments can be made
+}
+
+impl SubscriptionStatus {
+	pub fn can_transition_to(&self, next: SubscriptionStatus) -> bool {
+		use SubscriptionStatus::*;
+
+		match self {
+			Trialing => matches!(next, Active | PastDue | Paused | PendingCancel | Ended),
+			Active => matches!(next, PastDue | Paused | PendingCancel | Ended),
+			PastDue => matches!(next, Active | Paused | PendingCancel | Ended),
+			Paused => matches!(next, Trialing | Active | PastDue | PendingCancel | Ended),
+			PendingCancel => matches!(next, Ended),
+			Ended => false,
+		}
+	}
+}
+
+
+// Subscription plan defined by a merchant, derived from the merchant and a plan id
+// Subscriptions to a plan are billed the plan price, instead of an amount provided on each execution
+#[account]
+pub struct Plan {
+	pub merchant: Pubkey,
+	pub organization: Pubkey, // Organization receiving a portion of the fees
+	pub mint: Pubkey,
+
+	pub price: u64, // Amount charged every period
+	pub billing_interval: BillingInterval, // Billing period
+	pub trial_period: u32, // Length of the free trial of new subscriptions, in seconds (0 for no trial)
+
+	pub active: bool, // Inactive plans do not accept new subscriptions (existing ones are still billed)
+	pub bump: u8,
+}
+
+impl Plan {
+	pub const LEN: usize = 8 // discriminator
+		+ 32 // merchant
+		+ 32 // organization
+		+ 32 // mint
+		+ 8 // price
+		+ BillingInterval::LEN // billing_interval
+		+ 4 // trial_period
+		+ 1 // active
+		+ 1; // bump
+}
+
+
+// On-chain record of a one-time payment, derived from the uuid of the payment
+// Optional: it is only created if the client provides it. Once it exists, the same uuid cannot be paid again
+#[account]
+pub struct PaymentReceipt {
+	pub payer: Pubkey,
+	pub merchant: Pubkey,
+	pub mint: Option<Pubkey>, // None for SOL payments
+
+	pub amount: u64, // Gross amount of the payment
+	pub fee_amount: u64, // Protocol fee
+	pub organization_fee_amount: u64, // Organization fee
+	pub merchant_amount: u64, // Amount sent to the merchant
+	pub refunded_amount: u64, // Cumulative amount refunded by the merchant (never exceeds amount)
+
+	pub timestamp: i64,
+	pub bump: u8,
+}
+
+impl PaymentReceipt {
+	pub const LEN: usize = 8 // discriminator
+		+ 32 // payer
+		+ 32 // merchant
+		+ 1 + 32 // mint
+		+ 8 // amount
+		+ 8 // fee_amount
+		+ 8 // organization_fee_amount
+		+ 8 // merchant_amount
+		+ 8 // refunded_amount
+		+ 8 // timestamp
+		+ 1; // bump
+
+	// Record a refund of `amount`: the total refunded can never exceed the amount of the payment
+	// Returns the total refunded
+	pub fn record_refund(&mut self, amount: u64) -> Result<u64> {
+		let total_refunded = self.refunded_amount.checked_add(amount).ok_or(crate::errors::QBitFlowError::Overflow)?;
+		if total_refunded > self.amount {
+			return err!(crate::errors::QBitFlowError::RefundExceedsPayment);
+		}
+
+		self.refunded_amount = total_refunded;
+		Ok(total_refunded)
+	}
+}
+
+
+// Pre-authorization (hold) of a token payment, derived from the uuid of the payment
+// The held funds are locked in a vault token account (derived from the same uuid) owned by the authority PDA, until they are captured by the merchant or voided
+#[account]
+pub struct PaymentAuthorization {
+	pub payer: Pubkey,
+	pub merchant: Pubkey,
+	pub mint: Pubkey,
+
+	pub amount: u64, // Amount held in the vault
+	pub expires_at: i64, // The authorization cannot be captured after this timestamp, and can then be voided without the merchant
+
+	pub bump: u8,
+}
+
+impl PaymentAuthorization {
+	pub const LEN: usize = 8 // discriminator
+		+ 32 // payer
+		+ 32 // merchant
+		+ 32 // mint
+		+ 8 // amount
+		+ 8 // expires_at
+		+ 1; // bump
+
+	// Split of a capture of `amount` (up to the held amount), fees computed on the captured amount
+	// Returns the fee, organization fee and merchant amounts, and the rest of the hold returned to the payer
+	pub fn split_capture(&self, amount: u64, fee_bps: u16, organization_fee_bps: u16) -> Result<(u64, u64, u64, u64)> {
+		let released_amount = self.amount.checked_sub(amount).ok_or(crate::errors::QBitFlowError::InvalidAmount)?;
+
+		let (fee_amount, org_fee_amount) = calculate_fee(amount, fee_bps, organization_fee_bps)?;
+		let merchant_amount = amount
+			.checked_sub(fee_amount)
+			.and_then(|x| x.checked_sub(org_fee_amount))
+			.ok_or(crate::errors::QBitFlowError::Overflow)?;
+
+		Ok((fee_amount, org_fee_amount, merchant_amount, released_amount))
+	}
+}
+
+
+// Escrow configuration, derived from the authority PDA
+// Kept in its own account so the Authority layout (used by every instruction) does not change
+// Created by set_arbiter, escrow disputes cannot be resolved until then
+#[account]
+pub struct EscrowConfig {
+	pub arbiter: Pubkey, // Settles escrow disputes
+	pub bump: u8,
+}
+
+impl EscrowConfig {
+	pub const LEN: usize = 8 // discriminator
+		+ 32 // arbiter
+		+ 1; // bump
+}
+
+
+// Escrow payment, derived from the uuid of the payment
+// The funds are locked in a vault token account (derived from the same uuid) owned by the authority PDA
+// They are released to the merchant (minus fees) by the payer, or by anyone once release_at has passed, unless the payer raised a dispute before then
+// Disputes are settled by the arbiter stored in the EscrowConfig account
+#[account]
+pub struct Escrow {
+	pub payer: Pubkey,
+	pub merchant: Pubkey,
+	pub organization: Pubkey,
+	pub mint: Pubkey,
+
+	pub amount: u64, // Amount held in the vault
+	pub fee_bps: u16, // Fees applied on release
+	pub organization_fee_bps: u16,
+
+	pub release_at: i64, // End of the dispute window, the funds can be released without the payer after this timestamp
+	pub disputed: bool,
+
+	pub bump: u8,
+}
+
+impl Escrow {
+	pub const LEN: usize = 8 // discriminator
+		+ 32 // payer
+		+ 32 // merchant
+		+ 32 // organization
+		+ 32 // mint
+		+ 8 // amount
+		+ 2 // fee_bps
+		+ 2 // organization_fee_bps
+		+ 8 // release_at
+		+ 1 // disputed
+		+ 1; // bump
+
+	// Split of `amount` released to the merchant, with the fees of the escrow
+	// Returns the fee, organization fee and merchant amounts (nothing for a zero amount)
+	pub fn split_release(&self, amount: u64) -> Result<(u64, u64, u64)> {
+		if amount == 0 {
+			return Ok((0, 0, 0));
+		}
+
+		let (fee_amount, org_fee_amount) = calculate_fee(amount, self.fee_bps, self.organization_fee_bps)?;
+		let merchant_amount = amount
+			.checked_sub(fee_amount)
+			.and_then(|x| x.checked_sub(org_fee_amount))
+			.ok_or(crate::errors::QBitFlowError::Overflow)?;
+
+		Ok((fee_amount, org_fee_amount, merchant_amount))
+	}
+
+	// Share of a disputed escrow released to the merchant, once `payer_amount` is returned to the payer (without fees)
+	pub fn merchant_share(&self, payer_amount: u64) -> Result<u64> {
+		self.amount.checked_sub(payer_amount).ok_or(crate::errors::QBitFlowError::InvalidAmount.into())
+	}
 }
 
 
@@ -79,8 +660,107 @@ impl Subscription {
 #[event]
 pub struct PaymentProcessed {
     pub uuid: [u8; 16],
+	pub amount: u64, // Gross amount of the payment (before protocol and organization fees)
+	pub merchant_amount: u64, // Net amount received by the merchant
+	pub fee_amount: u64, // Net amount received by the fee recipient
+	pub organization_fee_amount: u64, // Net amount received by the organization
+}
+
+
+// Net amount received by a recipient of a split payment
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SplitPayout {
+	pub recipient: Pubkey,
+	pub amount: u64,
+}
+
+#[event]
+pub struct SplitPaymentProcessed {
+	pub uuid: [u8; 16],
+	pub amount: u64, // Gross amount of the payment (before protocol and organization fees)
+	pub fee_amount: u64, // Net amount received by the fee recipient
+	pub organization_fee_amount: u64, // Net amount received by the organization
+	pub payouts: Vec<SplitPayout>, // Net amount received by each recipient, in the order of the shares
+}
+
+#[event]
+pub struct PaymentRefunded {
+	pub uuid: [u8; 16],
+	pub amount: u64, // Amount refunded by this refund
+	pub total_refunded: u64, // Cumulative amount refunded for the payment
+}
+
+
+#[event]
+pub struct PaymentAuthorized {
+	pub uuid: [u8; 16],
+	pub amount: u64, // Amount held
+	pub expires_at: i64,
+}
+
+#[event]
+pub struct PaymentCaptured {
+	pub uuid: [u8; 16],
+	pub amount: u64, // Amount captured (before protocol and organization fees)
+	pub merchant_amount: u64, // Net amount received by the merchant
+	pub fee_amount: u64, // Net amount received by the fee recipient
+	pub organization_fee_amount: u64, // Net amount received by the organization
+	pub released_amount: u64, // Amount of the hold returned to the payer
 }
 
+#[event]
+pub struct AuthorizationVoided {
+	pub uuid: [u8; 16],
+	pub released_amount: u64, // Amount of the hold returned to the payer
+	pub expired: bool, // Whether the authorization was voided after its expiration
+}
+
+
+#[event]
+pub struct EscrowCreated {
+	pub uuid: [u8; 16],
+	pub amount: u64, // Amount held in escrow
+	pub release_at: i64,
+}
+
+#[event]
+pub struct EscrowReleased {
+	pub uuid: [u8; 16],
+	pub amount: u64, // Amount released (before protocol and organization fees)
+	pub merchant_amount: u64, // Net amount received by the merchant
+	pub fee_amount: u64, // Net amount received by the fee recipient
+	pub organization_fee_amount: u64, // Net amount received by the organization
+}
+
+#[event]
+pub struct EscrowDisputeRaised {
+	pub uuid: [u8; 16],
+}
+
+#[event]
+pub struct EscrowDisputeResolved {
+	pub uuid: [u8; 16],
+	pub payer_amount: u64, // Amount returned to the payer
+	pub merchant_amount: u64, // Net amount received by the merchant
+	pub fee_amount: u64, // Net amount received by the fee recipient
+	pub organization_fee_amount: u64, // Net amount received by the organization
+}
+
+
+#[event]
+pub struct PlanCreated {
+	pub plan: Pubkey,
+	pub merchant: Pubkey,
+	pub price: u64,
+	pub billing_interval: BillingInterval,
+	pub trial_period: u32,
+}
+
+#[event]
+pub struct PlanActiveUpdated {
+	pub plan: Pubkey,
+	pub active: bool,
+}
 
 #[event]
 pub struct SubscriptionCreated {
@@ -89,16 +769,90 @@ pub struct SubscriptionCreated {
     pub initial_allowance: u64,
 }
 
+#[event]
+pub struct TrialStarted {
+	pub uuid: [u8; 16],
+	pub trial_ends_at: i64,
+}
+
+#[event]
+pub struct TrialConverted {
+	pub uuid: [u8; 16],
+	pub amount: u64, // Amount of the first payment after the trial
+}
+
 #[event]
 pub struct SubscriptionPaymentProcessed {
     pub uuid: [u8; 16],
     pub next_payment_due: i64,
     pub remaining_allowance: u64,
+	pub amount: u64, // Amount billed for the period
+	pub merchant_amount: u64, // Net amount received by the merchant
+	pub fee_amount: u64, // Net amount received by the fee recipient
+	pub organization_fee_amount: u64, // Net amount received by the organization
+	pub catch_up_policy: CatchUpPolicy, // Catch-up policy of the subscription
+	pub skipped_periods: u64, // Missed periods skipped by the catch-up policy (not charged)
+	pub credit_applied: u64, // Part of the amount paid with the credit left by a plan downgrade
+}
+
+#[event]
+pub struct SubscriptionPlanChanged {
+	pub uuid: [u8; 16],
+	pub plan: Pubkey, // New plan
+	pub price: u64, // Price of the new plan
+	pub prorated_charge: u64, // Price of the new plan for the rest of the current period
+	pub prorated_credit: u64, // Unused part of the last payment for the rest of the current period
+	pub amount_charged: u64, // Amount charged now (upgrade)
+	pub credit: u64, // Credit left for the next payments (downgrade)
+}
+
+#[event]
+pub struct SubscriptionCompleted {
+	pub uuid: [u8; 16],
+	pub payments_made: u32,
+}
+
+#[event]
+pub struct SubscriptionPaused {
+	pub uuid: [u8; 16],
+	pub paused_at: i64,
+}
+
+#[event]
+pub struct SubscriptionResumed {
+	pub uuid: [u8; 16],
+	pub next_payment_due: i64, // Moved forward by the time spent paused
+}
+
+#[event]
+pub struct SubscriptionPaymentFailed {
+	pub uuid: [u8; 16],
+	pub reason: PaymentFailureReason,
+	pub failed_attempts: u8,
+	pub next_retry_at: i64, // 0 if the subscription was canceled
+}
+
+#[event]
+pub struct SubscriptionStatusChanged {
+	pub uuid: [u8; 16],
+	pub previous_status: SubscriptionStatus,
+	pub status: SubscriptionStatus,
+}
+
+#[event]
+pub struct PaygSubscriptionSettled {
+	pub uuid: [u8; 16],
+	pub amount: u64, // Final usage charge
+	// Net amounts actually received by each party (after the Token-2022 transfer fee, if any)
+	pub merchant_amount: u64,
+	pub fee_amount: u64,
+	pub organization_fee_amount: u64,
 }
 
 #[event]
 pub struct SubscriptionCancelled {
 	pub uuid: [u8; 16],
+	pub reason: CancellationReason, // Who (or what) canceled the subscription
 }
 
 #[event]
@@ -107,11 +861,46 @@ pub struct AllowanceIncreased {
     pub uuid: [u8; 16],
 }
 
+#[event]
+pub struct SubscriptionTransferred {
+	pub uuid: [u8; 16],
+	pub previous_subscriber: Pubkey,
+	pub subscriber: Pubkey,
+}
+
+#[event]
+pub struct AllAllowancesRevoked {
+	pub subscriber: Pubkey,
+	pub mint: Pubkey,
+	pub subscriptions_closed: u32, // Number of subscriptions canceled
+}
+
+#[event]
+pub struct AllowanceDecreased {
+	pub new_allowance: u64,
+	pub uuid: [u8; 16],
+}
+
 #[event]
 pub struct ComputeRefundFailed {
 	pub uuid: [u8; 16],
 }
 
+#[event]
+pub struct PriceChangeProposed {
+	pub uuid: [u8; 16],
+	pub price: u64,
+	pub effective_at: i64,
+}
+
+#[event]
+pub struct PriceChangeAccepted {
+	pub uuid: [u8; 16],
+	pub price: u64,
+	pub new_max_amount: u64,
+	pub new_allowance: u64,
+}
+
 #[event]
 pub struct MaxAmountUpdated {
 	pub uuid: [u8; 16],
@@ -152,14 +941,546 @@ pub fn calculate_fee(amount: u64, fee_bps: u16, organization_fee_bps: u16) -> Re
 pub fn create_subscription_hash(
 	merchant_token_account: &Pubkey,
 	subscriber_token_account: &Pubkey,
-	frequency: u32,
+	billing_interval: &BillingInterval,
 	organization_token_account: &Pubkey,
 ) -> [u8; 32] {
-	let freq_bytes = frequency.to_le_bytes();
-    return hashv(&[
+	let interval_bytes = billing_interval.hash_bytes();
+    hashv(&[
         merchant_token_account.as_ref(),
         subscriber_token_account.as_ref(),
-        &freq_bytes,
+        &interval_bytes,
         organization_token_account.as_ref(),
     ]).to_bytes()
-}
\ No newline at end of file
+}
+
+
+// Calendar helpers (proleptic Gregorian calendar, days counted from 1970-01-01)
+// See http://howardhinnant.github.io/date_algorithms.html
+
+fn is_leap_year(year: i64) -> bool {
+	(year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+	match month {
+		4 | 6 | 9 | 11 => 30,
+		2 if is_leap_year(year) => 29,
+		2 => 28,
+		_ => 31,
+	}
+}
+
+// (year, month, day) of a number of days since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+	let days = days + 719468;
+	let era = days.div_euclid(146097);
+	let day_of_era = days.rem_euclid(146097);
+	let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+	let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+	let shifted_month = (5 * day_of_year + 2) / 153; // March = 0
+	let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+	let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+	let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+	(year, month, day)
+}
+
+// Number of days since 1970-01-01 of a (year, month, day)
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+	let year = if month <= 2 { year - 1 } else { year };
+	let era = year.div_euclid(400);
+	let year_of_era = year.rem_euclid(400);
+	let shifted_month = (month + 9) % 12; // March = 0
+	let day_of_year = (153 * shifted_month + 2) / 5 + day - 1;
+	let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+	era * 146097 + day_of_era - 719468
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use super::*;
+
+	// Timestamp of a date (midnight UTC)
+	fn date(year: i64, month: i64, day: i64) -> i64 {
+		days_from_civil(year, month, day) * 86400
+	}
+
+	const MONTHLY_ON_31: BillingInterval = BillingInterval::Months { count: 1, anchor_day: 31 };
+	const YEARLY_ON_FEB_29: BillingInterval = BillingInterval::Years { count: 1, anchor_month: 2, anchor_day: 29 };
+
+	// Size of an account once serialized (discriminator included)
+	fn account_len<T: AnchorSerialize>(account: &T) -> usize {
+		8 + account.try_to_vec().unwrap().len()
+	}
+
+	// Active regular subscription, paused on its due date
+	pub(crate) fn paused_subscription(billing_interval: BillingInterval, next_payment_due: i64) -> Subscription {
+		Subscription {
+			subscriber: Pubkey::new_unique(),
+			next_payment_due,
+			allowance: 10_000,
+			used_allowance: 0,
+			subscription_hash: [0; 32],
+			stopped: false,
+			max_amount: 100,
+			last_payment_amount: 100,
+			bump: 255,
+			version: Subscription::VERSION,
+			merchant: Pubkey::new_unique(),
+			merchant_token_account: Pubkey::new_unique(),
+			organization_token_account: Pubkey::new_unique(),
+			mint: Pubkey::new_unique(),
+			billing_interval,
+			is_payg: false,
+			plan: Pubkey::default(),
+			trial_ends_at: 0,
+			paused_at: next_payment_due,
+			status: SubscriptionStatus::Paused,
+			status_before_pause: SubscriptionStatus::Active,
+			dunning_config: None,
+			failed_attempts: 0,
+			retry_at: 0,
+			catch_up_policy: CatchUpPolicy::ChargeAll,
+			max_payments: 0,
+			ends_at: 0,
+			payments_made: 1,
+			period_start: billing_interval.previous_due(next_payment_due).unwrap(),
+			credit: 0,
+			pending_price_change: None,
+		}
+	}
+
+	fn payment_receipt(amount: u64) -> PaymentReceipt {
+		PaymentReceipt {
+			payer: Pubkey::new_unique(),
+			merchant: Pubkey::new_unique(),
+			mint: Some(Pubkey::new_unique()),
+			amount,
+			fee_amount: 0,
+			organization_fee_amount: 0,
+			merchant_amount: amount,
+			refunded_amount: 0,
+			timestamp: 0,
+			bump: 255,
+		}
+	}
+
+	#[test]
+	fn leap_years() {
+		assert!(is_leap_year(2024));
+		assert!(is_leap_year(2000));
+		assert!(!is_leap_year(1900));
+		assert!(!is_leap_year(2023));
+		assert_eq!(days_in_month(2024, 2), 29);
+		assert_eq!(days_in_month(2100, 2), 28);
+	}
+
+	#[test]
+	fn civil_round_trip() {
+		assert_eq!(civil_from_days(0), (1970, 1, 1));
+		assert_eq!(civil_from_days(-1), (1969, 12, 31));
+		assert_eq!(civil_from_days(days_from_civil(2000, 2, 29)), (2000, 2, 29));
+
+		for days in (-800_000..800_000).step_by(97) {
+			let (year, month, day) = civil_from_days(days);
+			assert_eq!(days_from_civil(year, month, day), days);
+		}
+	}
+
+	#[test]
+	fn months_clamp_to_month_end() {
+		let time_of_day = 3600 * 13 + 42;
+
+		let feb = MONTHLY_ON_31.next_due(date(2024, 1, 31) + time_of_day).unwrap();
+		assert_eq!(feb, date(2024, 2, 29) + time_of_day);
+
+		// The anchor day is restored after a short month
+		assert_eq!(MONTHLY_ON_31.next_due(feb).unwrap(), date(2024, 3, 31) + time_of_day);
+		assert_eq!(MONTHLY_ON_31.next_due(date(2023, 1, 31)).unwrap(), date(2023, 2, 28));
+	}
+
+	#[test]
+	fn years_keep_their_anchor() {
+		let mut due = date(2024, 2, 29);
+		let mut dues = vec![];
+		for _ in 0..4 {
+			due = YEARLY_ON_FEB_29.next_due(due).unwrap();
+			dues.push(due);
+		}
+
+		assert_eq!(dues, vec![date(2025, 2, 28), date(2026, 2, 28), date(2027, 2, 28), date(2028, 2, 29)]);
+	}
+
+	#[test]
+	fn first_payment_due() {
+		let weekly = BillingInterval::Seconds(MIN_FREQUENCY);
+		let monthly_on_1 = BillingInterval::Months { count: 1, anchor_day: 1 };
+		let now = date(2024, 1, 10) + 3600;
+		let trial_ends_at = now + 14 * 86400;
+
+		// Regular subscriptions: immediately, or at the end of the trial
+		assert_eq!(weekly.first_due(false, now, 0).unwrap(), now);
+		assert_eq!(weekly.first_due(false, now, trial_ends_at).unwrap(), trial_ends_at);
+
+		// On the next anchor date for calendar intervals
+		assert_eq!(monthly_on_1.first_due(false, now, 0).unwrap(), date(2024, 2, 1) + 3600);
+		assert_eq!(monthly_on_1.first_due(false, now, trial_ends_at).unwrap(), date(2024, 2, 1) + 3600);
+		assert_eq!(monthly_on_1.first_due(false, date(2024, 1, 25), date(2024, 2, 8)).unwrap(), date(2024, 3, 1));
+
+		// Pay-as-you-go subscriptions are billed at the end of their first period
+		assert_eq!(weekly.first_due(true, now, 0).unwrap(), now + MIN_FREQUENCY as i64);
+		assert_eq!(monthly_on_1.first_due(true, now, 0).unwrap(), date(2024, 2, 1) + 3600);
+	}
+
+	#[test]
+	fn align_to_anchor() {
+		let monthly_on_1 = BillingInterval::Months { count: 1, anchor_day: 1 };
+		assert_eq!(monthly_on_1.align(date(2024, 1, 30) + 60), date(2024, 2, 1) + 60);
+		assert_eq!(monthly_on_1.align(date(2024, 12, 2)), date(2025, 1, 1));
+		assert_eq!(monthly_on_1.align(date(2024, 3, 1) + 60), date(2024, 3, 1) + 60);
+
+		assert_eq!(MONTHLY_ON_31.align(date(2023, 2, 10)), date(2023, 2, 28));
+
+		assert_eq!(YEARLY_ON_FEB_29.align(date(2025, 3, 1)), date(2026, 2, 28));
+		assert_eq!(YEARLY_ON_FEB_29.align(date(2027, 6, 1)), date(2028, 2, 29));
+		assert_eq!(YEARLY_ON_FEB_29.align(date(2024, 2, 29)), date(2024, 2, 29));
+
+		assert_eq!(BillingInterval::Seconds(MIN_FREQUENCY).align(12345), 12345);
+	}
+
+	#[test]
+	fn before_1970() {
+		let time_of_day = 7200;
+
+		assert_eq!(MONTHLY_ON_31.next_due(date(1969, 11, 30) + time_of_day).unwrap(), date(1969, 12, 31) + time_of_day);
+		assert_eq!(MONTHLY_ON_31.next_due(date(1969, 12, 31) + time_of_day).unwrap(), date(1970, 1, 31) + time_of_day);
+		assert_eq!(YEARLY_ON_FEB_29.next_due(date(1968, 2, 29)).unwrap(), date(1969, 2, 28));
+		assert_eq!(MONTHLY_ON_31.align(-1), date(1969, 12, 31) + 86399);
+	}
+
+	#[test]
+	fn advance_multiple_periods() {
+		let quarterly = BillingInterval::Months { count: 3, anchor_day: 31 };
+		assert_eq!(quarterly.advance(date(2023, 11, 30), 0).unwrap(), date(2023, 11, 30));
+		assert_eq!(quarterly.advance(date(2023, 11, 30), 1).unwrap(), date(2024, 2, 29));
+		assert_eq!(quarterly.advance(date(2023, 11, 30), 2).unwrap(), date(2024, 5, 31));
+		assert_eq!(quarterly.advance(date(2023, 11, 30), 5).unwrap(), date(2025, 2, 28));
+
+		let weekly = BillingInterval::Seconds(MIN_FREQUENCY);
+		assert_eq!(weekly.advance(-100, 3).unwrap(), -100 + 3 * MIN_FREQUENCY as i64);
+	}
+
+	#[test]
+	fn previous_due_is_on_the_anchor() {
+		assert_eq!(MONTHLY_ON_31.previous_due(date(2024, 3, 31)).unwrap(), date(2024, 2, 29));
+		assert_eq!(MONTHLY_ON_31.previous_due(date(2024, 2, 29)).unwrap(), date(2024, 1, 31));
+		assert_eq!(MONTHLY_ON_31.previous_due(date(1970, 1, 31) + 60).unwrap(), date(1969, 12, 31) + 60);
+		assert_eq!(YEARLY_ON_FEB_29.previous_due(date(2025, 2, 28)).unwrap(), date(2024, 2, 29));
+
+		let quarterly = BillingInterval::Months { count: 3, anchor_day: 15 };
+		assert_eq!(quarterly.previous_due(date(2024, 2, 15)).unwrap(), date(2023, 11, 15));
+
+		// The period ending at a due date starts one period earlier
+		for due in [date(2024, 3, 31), date(2024, 4, 30), date(2025, 2, 28)] {
+			let start = MONTHLY_ON_31.previous_due(due).unwrap();
+			assert_eq!(MONTHLY_ON_31.next_due(start).unwrap(), due);
+		}
+
+		assert_eq!(BillingInterval::Seconds(MIN_FREQUENCY).previous_due(0).unwrap(), -(MIN_FREQUENCY as i64));
+	}
+
+	#[test]
+	fn notice_covers_a_full_period() {
+		let monthly_on_1 = BillingInterval::Months { count: 1, anchor_day: 1 };
+
+		// Proposed on Jan 30, next billed on Feb 1: the notice runs until Mar 1, not Feb 1
+		assert_eq!(monthly_on_1.notice_end(date(2024, 2, 1), date(2024, 1, 30)).unwrap(), date(2024, 3, 1));
+
+		// Overdue payment, billed on the next anchor date
+		assert_eq!(monthly_on_1.notice_end(date(2024, 1, 1), date(2024, 1, 30)).unwrap(), date(2024, 3, 1));
+		assert_eq!(YEARLY_ON_FEB_29.notice_end(date(2025, 2, 28), date(2024, 12, 1)).unwrap(), date(2026, 2, 28));
+
+		let weekly = BillingInterval::Seconds(MIN_FREQUENCY);
+		assert_eq!(weekly.notice_end(1000, 500).unwrap(), 1000 + MIN_FREQUENCY as i64);
+		assert_eq!(weekly.notice_end(1000, 5000).unwrap(), 5000 + MIN_FREQUENCY as i64);
+	}
+
+	#[test]
+	fn periods_due() {
+		let first_due = date(2024, 1, 31);
+		assert_eq!(MONTHLY_ON_31.periods_due(first_due, first_due - 1).unwrap(), 0);
+		assert_eq!(MONTHLY_ON_31.periods_due(first_due, first_due).unwrap(), 1);
+		assert_eq!(MONTHLY_ON_31.periods_due(first_due, date(2024, 2, 28)).unwrap(), 1);
+		assert_eq!(MONTHLY_ON_31.periods_due(first_due, date(2024, 2, 29)).unwrap(), 2);
+		assert_eq!(MONTHLY_ON_31.periods_due(first_due, date(2024, 5, 30)).unwrap(), 4);
+
+		let weekly = BillingInterval::Seconds(MIN_FREQUENCY);
+		assert_eq!(weekly.periods_due(0, 3 * MIN_FREQUENCY as i64 - 1).unwrap(), 3);
+	}
+
+	#[test]
+	fn catch_up_skipped_periods() {
+		assert_eq!(CatchUpPolicy::ChargeAll.periods_to_skip(4), 0);
+		assert_eq!(CatchUpPolicy::SkipToCurrent.periods_to_skip(4), 3);
+		assert_eq!(CatchUpPolicy::MaxPeriods(2).periods_to_skip(4), 2);
+		assert_eq!(CatchUpPolicy::MaxPeriods(5).periods_to_skip(4), 0);
+
+		// Nothing is skipped when a single period (or none) is owed
+		for policy in [CatchUpPolicy::ChargeAll, CatchUpPolicy::SkipToCurrent, CatchUpPolicy::MaxPeriods(1)] {
+			assert_eq!(policy.periods_to_skip(1), 0);
+			assert_eq!(policy.periods_to_skip(0), 0);
+		}
+
+		// Executed four periods late: the period charged is the current one, or the oldest one within the limit
+		let first_due = date(2024, 1, 31);
+		let periods_owed = MONTHLY_ON_31.periods_due(first_due, date(2024, 5, 30)).unwrap();
+		assert_eq!(MONTHLY_ON_31.advance(first_due, CatchUpPolicy::SkipToCurrent.periods_to_skip(periods_owed)).unwrap(), date(2024, 4, 30));
+		assert_eq!(MONTHLY_ON_31.advance(first_due, CatchUpPolicy::MaxPeriods(2).periods_to_skip(periods_owed)).unwrap(), date(2024, 3, 31));
+		assert_eq!(MONTHLY_ON_31.advance(first_due, CatchUpPolicy::ChargeAll.periods_to_skip(periods_owed)).unwrap(), first_due);
+	}
+
+	#[test]
+	fn plan_len() {
+		let plan = |billing_interval| Plan {
+			merchant: Pubkey::new_unique(),
+			organization: Pubkey::new_unique(),
+			mint: Pubkey::new_unique(),
+			price: 1000,
+			billing_interval,
+			trial_period: 0,
+			active: true,
+			bump: 255,
+		};
+
+		// The size of the account is the one of the largest billing interval
+		assert_eq!(account_len(&plan(BillingInterval::Seconds(MIN_FREQUENCY))), Plan::LEN);
+		assert!(account_len(&plan(YEARLY_ON_FEB_29)) <= Plan::LEN);
+		assert!(account_len(&plan(MONTHLY_ON_31)) <= Plan::LEN);
+	}
+
+	#[test]
+	fn billing_interval_validation() {
+		assert!(BillingInterval::Seconds(MIN_FREQUENCY).validate().is_ok());
+		assert!(BillingInterval::Seconds(MIN_FREQUENCY - 1).validate().is_err());
+
+		assert!(MONTHLY_ON_31.validate().is_ok());
+		assert!(BillingInterval::Months { count: 0, anchor_day: 1 }.validate().is_err());
+		assert!(BillingInterval::Months { count: 1, anchor_day: 0 }.validate().is_err());
+		assert!(BillingInterval::Months { count: 1, anchor_day: 32 }.validate().is_err());
+
+		assert!(YEARLY_ON_FEB_29.validate().is_ok());
+		assert!(BillingInterval::Years { count: 1, anchor_month: 2, anchor_day: 30 }.validate().is_err());
+		assert!(BillingInterval::Years { count: 1, anchor_month: 13, anchor_day: 1 }.validate().is_err());
+		assert!(BillingInterval::Years { count: 0, anchor_month: 1, anchor_day: 1 }.validate().is_err());
+	}
+
+	#[test]
+	fn subscription_len() {
+		// Seconds is the largest billing interval variant
+		let mut subscription = paused_subscription(BillingInterval::Seconds(MIN_FREQUENCY), date(2024, 2, 29));
+		subscription.dunning_config = Some(DunningConfig { max_retries: 3, retry_interval: 86400 });
+		subscription.catch_up_policy = CatchUpPolicy::MaxPeriods(2);
+		subscription.pending_price_change = Some(PriceChange { price: 200, effective_at: date(2025, 2, 28), accepted_max_amount: 0 });
+		assert_eq!(account_len(&subscription), Subscription::LEN);
+	}
+
+	#[test]
+	fn dunning_backoff() {
+		let dunning_config = DunningConfig { max_retries: 3, retry_interval: 3600 };
+
+		assert_eq!(dunning_config.retry_delay(1), 3600);
+		assert_eq!(dunning_config.retry_delay(2), 7200);
+		assert_eq!(dunning_config.retry_delay(3), 14400);
+		assert_eq!(dunning_config.retry_delay(17), 3600 << 16);
+		assert_eq!(dunning_config.retry_delay(u8::MAX), 3600 << 16);
+
+		// Canceled after max_retries failed retries (the first failure is not a retry)
+		assert!(!dunning_config.exhausted(1));
+		assert!(!dunning_config.exhausted(3));
+		assert!(dunning_config.exhausted(4));
+		assert!(DunningConfig { max_retries: 0, retry_interval: 3600 }.exhausted(1));
+		assert!(!DunningConfig { max_retries: u8::MAX, retry_interval: 3600 }.exhausted(u8::MAX));
+	}
+
+	#[test]
+	fn status_transitions() {
+		use SubscriptionStatus::*;
+
+		let statuses = [Trialing, Active, PastDue, Paused, PendingCancel, Ended];
+		let valid = [
+			(Trialing, Active), (Trialing, PastDue), (Trialing, Paused), (Trialing, PendingCancel), (Trialing, Ended),
+			(Active, PastDue), (Active, Paused), (Active, PendingCancel), (Active, Ended),
+			(PastDue, Active), (PastDue, Paused), (PastDue, PendingCancel), (PastDue, Ended),
+			(Paused, Trialing), (Paused, Active), (Paused, PastDue), (Paused, PendingCancel), (Paused, Ended),
+			(PendingCancel, Ended),
+		];
+
+		for from in statuses {
+			for to in statuses {
+				assert_eq!(from.can_transition_to(to), valid.contains(&(from, to)), "{:?} -> {:?}", from, to);
+			}
+		}
+	}
+
+	#[test]
+	fn set_status() {
+		let mut subscription = paused_subscription(BillingInterval::Seconds(MIN_FREQUENCY), date(2024, 1, 10));
+		subscription.status = SubscriptionStatus::Active;
+
+		// Setting the current status does nothing, even if the transition is not valid
+		subscription.set_status([0; 16], SubscriptionStatus::Active).unwrap();
+		assert_eq!(subscription.status, SubscriptionStatus::Active);
+
+		subscription.set_status([0; 16], SubscriptionStatus::PendingCancel).unwrap();
+		assert_eq!(subscription.status, SubscriptionStatus::PendingCancel);
+
+		// Invalid transitions leave the status unchanged
+		assert_eq!(subscription.set_status([0; 16], SubscriptionStatus::Active).unwrap_err(), crate::errors::QBitFlowError::InvalidStatusTransition.into());
+		assert_eq!(subscription.status, SubscriptionStatus::PendingCancel);
+
+		subscription.set_status([0; 16], SubscriptionStatus::Ended).unwrap();
+		subscription.set_status([0; 16], SubscriptionStatus::Ended).unwrap();
+		assert_eq!(subscription.set_status([0; 16], SubscriptionStatus::Trialing).unwrap_err(), crate::errors::QBitFlowError::InvalidStatusTransition.into());
+	}
+
+	#[test]
+	fn resume_shifts_the_schedule() {
+		let day = 86400;
+
+		// Seconds intervals keep the same time left
+		let mut subscription = paused_subscription(BillingInterval::Seconds(7 * day as u32), date(2024, 1, 10));
+		subscription.retry_at = date(2024, 1, 11);
+		subscription.shift_schedule(3 * day).unwrap();
+		assert_eq!(subscription.next_payment_due, date(2024, 1, 13));
+		assert_eq!(subscription.retry_at, date(2024, 1, 14));
+		assert_eq!(subscription.trial_ends_at, 0);
+
+		// Calendar intervals move to the next anchor date
+		let mut subscription = paused_subscription(MONTHLY_ON_31, date(2024, 1, 31));
+		subscription.shift_schedule(3 * day).unwrap();
+		assert_eq!(subscription.next_payment_due, date(2024, 2, 29));
+
+		// The trial is extended by the paused time
+		let mut subscription = paused_subscription(BillingInterval::Seconds(7 * day as u32), date(2024, 1, 10));
+		subscription.status_before_pause = SubscriptionStatus::Trialing;
+		subscription.trial_ends_at = date(2024, 1, 10);
+		subscription.shift_schedule(day).unwrap();
+		assert_eq!(subscription.trial_ends_at, date(2024, 1, 11));
+	}
+
+	#[test]
+	fn resume_past_the_end_date() {
+		let day = 86400;
+
+		// The final period of a pay-as-you-go subscription is billed at its end date
+		let mut subscription = paused_subscription(BillingInterval::Seconds(7 * day as u32), date(2024, 1, 10));
+		subscription.is_payg = true;
+		subscription.ends_at = date(2024, 1, 12);
+		subscription.shift_schedule(3 * day).unwrap();
+		assert_eq!(subscription.next_payment_due, date(2024, 1, 12));
+
+		// No payment of a regular subscription can be due at or after its end date
+		let mut subscription = paused_subscription(MONTHLY_ON_31, date(2024, 1, 31));
+		subscription.ends_at = date(2024, 2, 29);
+		assert_eq!(subscription.shift_schedule(day).unwrap_err(), crate::errors::QBitFlowError::ResumePastEndDate.into());
+		let mut subscription = paused_subscription(MONTHLY_ON_31, date(2024, 1, 31));
+		subscription.ends_at = date(2024, 3, 1);
+		subscription.shift_schedule(day).unwrap();
+		assert_eq!(subscription.next_payment_due, date(2024, 2, 29));
+
+		assert_eq!(subscription.shift_schedule(i64::MAX).unwrap_err(), crate::errors::QBitFlowError::Overflow.into());
+	}
+
+	#[test]
+	fn payment_receipt_len() {
+		assert_eq!(account_len(&payment_receipt(1000)), PaymentReceipt::LEN);
+	}
+
+	#[test]
+	fn capture_up_to_the_held_amount() {
+		let authorization = PaymentAuthorization {
+			payer: Pubkey::new_unique(),
+			merchant: Pubkey::new_unique(),
+			mint: Pubkey::new_unique(),
+			amount: 10_000,
+			expires_at: 0,
+			bump: 255,
+		};
+		assert_eq!(account_len(&authorization), PaymentAuthorization::LEN);
+
+		// Partial capture: 1% fee, then 2% of the rest to the organization, the rest of the hold goes back to the payer
+		assert_eq!(authorization.split_capture(6_000, 100, 200).unwrap(), (60, 118, 5_822, 4_000));
+
+		// Full capture, nothing released
+		let (fee_amount, org_fee_amount, merchant_amount, released_amount) = authorization.split_capture(10_000, 100, 0).unwrap();
+		assert_eq!((fee_amount, org_fee_amount, merchant_amount, released_amount), (100, 0, 9_900, 0));
+
+		// The fee is never below the minimum contract fee
+		assert_eq!(authorization.split_capture(10_000, 0, 0).unwrap(), (75, 0, 9_925, 0));
+
+		// Above the held amount
+		assert_eq!(authorization.split_capture(10_001, 100, 0).unwrap_err(), crate::errors::QBitFlowError::InvalidAmount.into());
+
+		// Nothing to capture (the authorization must be voided instead)
+		assert_eq!(authorization.split_capture(0, 100, 0).unwrap_err(), crate::errors::QBitFlowError::ZeroAmount.into());
+	}
+
+	#[test]
+	fn escrow_dispute_split() {
+		let escrow = Escrow {
+			payer: Pubkey::new_unique(),
+			merchant: Pubkey::new_unique(),
+			organization: Pubkey::new_unique(),
+			mint: Pubkey::new_unique(),
+			amount: 10_000,
+			fee_bps: 100,
+			organization_fee_bps: 200,
+			release_at: 0,
+			disputed: true,
+			bump: 255,
+		};
+		assert_eq!(account_len(&escrow), Escrow::LEN);
+		assert_eq!(account_len(&EscrowConfig { arbiter: Pubkey::new_unique(), bump: 255 }), EscrowConfig::LEN);
+
+		// Released without a dispute: fees on the whole amount
+		assert_eq!(escrow.split_release(10_000).unwrap(), (100, 198, 9_702));
+
+		// The payer's share is returned without fees, the fees only apply to the merchant's share
+		let merchant_share = escrow.merchant_share(4_000).unwrap();
+		assert_eq!(merchant_share, 6_000);
+		let (fee_amount, org_fee_amount, merchant_amount) = escrow.split_release(merchant_share).unwrap();
+		assert_eq!((fee_amount, org_fee_amount, merchant_amount), (60, 118, 5_822));
+		assert_eq!(4_000 + fee_amount + org_fee_amount + merchant_amount, escrow.amount);
+
+		// Everything returned to the payer: nothing released, no fees
+		assert_eq!(escrow.merchant_share(10_000).unwrap(), 0);
+		assert_eq!(escrow.split_release(0).unwrap(), (0, 0, 0));
+
+		// Nothing returned to the payer
+		assert_eq!(escrow.merchant_share(0).unwrap(), 10_000);
+
+		// More than the escrow holds
+		assert_eq!(escrow.merchant_share(10_001).unwrap_err(), crate::errors::QBitFlowError::InvalidAmount.into());
+	}
+
+	#[test]
+	fn refunds_capped_by_the_payment() {
+		let mut receipt = payment_receipt(1000);
+
+		assert_eq!(receipt.record_refund(400).unwrap(), 400);
+		assert_eq!(receipt.record_refund(600).unwrap(), 1000);
+
+		// Fully refunded, nothing more can be refunded
+		assert_eq!(receipt.record_refund(1).unwrap_err(), crate::errors::QBitFlowError::RefundExceedsPayment.into());
+		assert_eq!(receipt.refunded_amount, 1000);
+
+		// A refund above what is left fails without being recorded
+		let mut receipt = payment_receipt(1000);
+		receipt.record_refund(700).unwrap();
+		assert_eq!(receipt.record_refund(301).unwrap_err(), crate::errors::QBitFlowError::RefundExceedsPayment.into());
+		assert_eq!(receipt.refunded_amount, 700);
+
+		receipt.refunded_amount = u64::MAX;
+		assert_eq!(receipt.record_refund(1).unwrap_err(), crate::errors::QBitFlowError::Overflow.into());
+	}
+}